@@ -46,20 +46,17 @@ fn main() {
         "rust",
         #[cfg(feature = "scala")]
         "scala",
+        #[cfg(feature = "tsx")]
+        "tsx",
         #[cfg(feature = "typescript")]
         "typescript",
     ] {
         let mut build = cc::Build::new();
 
         let tree_sitter: PathBuf = match *lang {
-            "typescript" => [
-                "vendor",
-                &format!("tree-sitter-{}", lang),
-                "typescript",
-                "src",
-            ]
-            .iter()
-            .collect(),
+            "tsx" | "typescript" => ["vendor", "tree-sitter-typescript", lang, "src"]
+                .iter()
+                .collect(),
             _ => ["vendor", &format!("tree-sitter-{}", lang), "src"]
                 .iter()
                 .collect(),
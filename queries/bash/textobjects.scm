@@ -0,0 +1,4 @@
+(function_definition
+  body: (compound_statement "{" _* @function.inner "}")) @function.outer
+
+(comment) @comment.outer
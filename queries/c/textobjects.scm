@@ -0,0 +1,17 @@
+(function_definition
+  body: (compound_statement "{" _* @function.inner "}")) @function.outer
+
+(struct_specifier
+  body: (field_declaration_list "{" _* @class.inner "}")) @class.outer
+
+(union_specifier
+  body: (field_declaration_list "{" _* @class.inner "}")) @class.outer
+
+(enum_specifier
+  body: (enumerator_list "{" _* @class.inner "}")) @class.outer
+
+(parameter_list (*) @parameter.inner)
+
+(argument_list (*) @parameter.inner)
+
+(comment) @comment.outer
@@ -0,0 +1,22 @@
+(function_declaration
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(method_declaration
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(func_literal
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(type_declaration
+  (type_spec
+    type: (struct_type (field_declaration_list "{" _* @class.inner "}")))) @class.outer
+
+(type_declaration
+  (type_spec
+    type: (interface_type (method_spec_list "{" _* @class.inner "}")))) @class.outer
+
+(parameter_list (*) @parameter.inner)
+
+(argument_list (*) @parameter.inner)
+
+(comment) @comment.outer
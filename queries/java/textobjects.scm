@@ -0,0 +1,26 @@
+(method_declaration
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(constructor_declaration
+  body: (constructor_body "{" _* @function.inner "}")) @function.outer
+
+(lambda_expression
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(lambda_expression
+  body: (*) @function.inner) @function.outer
+
+(class_declaration
+  body: (class_body "{" _* @class.inner "}")) @class.outer
+
+(interface_declaration
+  body: (interface_body "{" _* @class.inner "}")) @class.outer
+
+(enum_declaration
+  body: (enum_body "{" _* @class.inner "}")) @class.outer
+
+(formal_parameters (*) @parameter.inner)
+
+(argument_list (*) @parameter.inner)
+
+(comment) @comment.outer
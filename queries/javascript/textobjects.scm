@@ -0,0 +1,32 @@
+(function_declaration
+  body: (statement_block "{" _* @function.inner "}")) @function.outer
+
+(generator_function_declaration
+  body: (statement_block "{" _* @function.inner "}")) @function.outer
+
+(function
+  body: (statement_block "{" _* @function.inner "}")) @function.outer
+
+(generator_function
+  body: (statement_block "{" _* @function.inner "}")) @function.outer
+
+(arrow_function
+  body: (statement_block "{" _* @function.inner "}")) @function.outer
+
+(arrow_function
+  body: (*) @function.inner) @function.outer
+
+(method_definition
+  body: (statement_block "{" _* @function.inner "}")) @function.outer
+
+(class_declaration
+  body: (class_body "{" _* @class.inner "}")) @class.outer
+
+(class
+  body: (class_body "{" _* @class.inner "}")) @class.outer
+
+(formal_parameters (*) @parameter.inner)
+
+(arguments (*) @parameter.inner)
+
+(comment) @comment.outer
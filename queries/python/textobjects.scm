@@ -0,0 +1,16 @@
+(function_definition
+  body: (block) @function.inner) @function.outer
+
+(lambda
+  body: (*) @function.inner) @function.outer
+
+(class_definition
+  body: (block) @class.inner) @class.outer
+
+(parameters (*) @parameter.inner)
+
+(lambda_parameters (*) @parameter.inner)
+
+(argument_list (*) @parameter.inner)
+
+(comment) @comment.outer
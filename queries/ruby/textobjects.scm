@@ -0,0 +1,29 @@
+(method) @function.outer
+
+(singleton_method) @function.outer
+
+(lambda
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(lambda
+  body: (do_block "do" _* @function.inner "end")) @function.outer
+
+(do_block) @function.outer
+
+(block) @function.outer
+
+(class) @class.outer
+
+(singleton_class) @class.outer
+
+(module) @class.outer
+
+(method_parameters (*) @parameter.inner)
+
+(lambda_parameters (*) @parameter.inner)
+
+(block_parameters (*) @parameter.inner)
+
+(argument_list (*) @parameter.inner)
+
+(comment) @comment.outer
@@ -0,0 +1,60 @@
+(line_comment) @comment
+(block_comment) @comment
+
+(string_literal) @string
+(raw_string_literal) @string
+(char_literal) @string
+(escape_sequence) @string.escape
+
+(integer_literal) @number
+(float_literal) @number
+(boolean_literal) @constant
+
+(primitive_type) @type.builtin
+(type_identifier) @type
+(lifetime) @label
+
+(attribute_item) @attribute
+
+(function_item name: (identifier) @function)
+(call_expression function: (identifier) @function)
+(call_expression function: (field_expression field: (field_identifier) @function.method))
+(call_expression function: (scoped_identifier name: (identifier) @function))
+(macro_invocation macro: (identifier) @function.macro "!" @function.macro)
+
+(field_identifier) @property
+
+((identifier) @constant
+ (#match? @constant "^[A-Z][A-Z0-9_]+$"))
+
+(self) @variable.builtin
+(mutable_specifier) @keyword
+
+"as" @keyword
+"break" @keyword
+"const" @keyword
+"continue" @keyword
+"else" @keyword
+"enum" @keyword
+"extern" @keyword
+"fn" @keyword
+"for" @keyword
+"if" @keyword
+"impl" @keyword
+"in" @keyword
+"let" @keyword
+"loop" @keyword
+"match" @keyword
+"mod" @keyword
+"move" @keyword
+"pub" @keyword
+"ref" @keyword
+"return" @keyword
+"static" @keyword
+"struct" @keyword
+"trait" @keyword
+"type" @keyword
+"unsafe" @keyword
+"use" @keyword
+"where" @keyword
+"while" @keyword
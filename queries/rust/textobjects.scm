@@ -0,0 +1,43 @@
+(function_item
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(closure_expression
+  body: (block "{" _* @function.inner "}")) @function.outer
+
+(closure_expression
+  body: (*) @function.inner) @function.outer
+
+(struct_item
+  body: (field_declaration_list "{" _* @class.inner "}")) @class.outer
+
+(struct_item
+  body: (ordered_field_declaration_list "(" _* @class.inner ")")) @class.outer
+
+(enum_item
+  body: (enum_variant_list "{" _* @class.inner "}")) @class.outer
+
+(union_item
+  body: (field_declaration_list "{" _* @class.inner "}")) @class.outer
+
+(trait_item
+  body: (declaration_list "{" _* @class.inner "}")) @class.outer
+
+(impl_item
+  body: (declaration_list "{" _* @class.inner "}")) @class.outer
+
+(mod_item
+  body: (declaration_list "{" _* @class.inner "}")) @class.outer
+
+(parameters (*) @parameter.inner)
+
+(closure_parameters (*) @parameter.inner)
+
+(type_parameters (*) @parameter.inner)
+
+(arguments (*) @parameter.inner)
+
+(type_arguments (*) @parameter.inner)
+
+(line_comment) @comment.outer
+
+(block_comment) @comment.outer
@@ -1,70 +1,376 @@
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
 use toml;
 use tree_sitter::Node;
 
-#[derive(Deserialize)]
+const PROJECT_CONFIG: &str = ".kak-tree.toml";
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Config {
+    query_dir: Option<String>,
+    grammar_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    languages: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    highlight: BTreeMap<String, String>,
     #[serde(default)]
-    filetype: HashMap<String, FiletypeConfig>,
+    filetype: BTreeMap<String, FiletypeConfig>,
+    #[serde(skip)]
+    resolved: BTreeMap<String, FiletypeConfig>,
 }
 
-#[derive(Clone, Deserialize, Default)]
+#[derive(Clone, Deserialize, Serialize, Default)]
 pub struct FiletypeConfig {
+    extends: Option<String>,
     blacklist: Option<Vec<String>>,
     whitelist: Option<Vec<String>>,
-    #[serde(default)]
-    group: HashMap<String, Vec<String>>,
+    injections: Option<String>,
+    // Tables go last, TOML doesn't allow values after them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    group: BTreeMap<String, Vec<String>>,
+    #[serde(skip)]
+    whitelist_kinds: Option<HashSet<String>>,
+    #[serde(skip)]
+    blacklist_kinds: Option<HashSet<String>>,
+    #[serde(skip)]
+    group_kinds: BTreeMap<String, Vec<String>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         let mut config = Config {
-            filetype: HashMap::default(),
+            query_dir: None,
+            grammar_dir: None,
+            languages: BTreeMap::default(),
+            highlight: BTreeMap::default(),
+            filetype: BTreeMap::default(),
+            resolved: BTreeMap::default(),
         };
+        config.languages.insert("sh".to_owned(), "bash".to_owned());
+        for (capture, face) in &[
+            ("attribute", "attribute"),
+            ("comment", "comment"),
+            ("constant", "value"),
+            ("function", "function"),
+            ("keyword", "keyword"),
+            ("label", "meta"),
+            ("module", "module"),
+            ("number", "value"),
+            ("operator", "operator"),
+            ("property", "variable"),
+            ("string", "string"),
+            ("type", "type"),
+            ("type.builtin", "builtin"),
+            ("variable.builtin", "builtin"),
+        ] {
+            config
+                .highlight
+                .insert(capture.to_string(), face.to_string());
+        }
         config
             .filetype
             .insert("default".to_owned(), FiletypeConfig::default());
+        config.resolved = config.filetype.clone();
         config
     }
 }
 
 impl Config {
-    pub fn load<P: AsRef<std::path::Path>>(path: P) -> Option<Self> {
-        let config = std::fs::read_to_string(path).ok()?;
-        let mut config: Config = toml::from_str(&config).ok()?;
-        if config.filetype.get("default").is_none() {
-            config
-                .filetype
-                .insert("default".to_owned(), FiletypeConfig::default());
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut config = Config::default();
+        config.merge(Config::parse(path.as_ref())?);
+        config.resolve()?;
+        Ok(config)
+    }
+
+    pub fn load_user(path: Option<&str>) -> Result<Self> {
+        match path {
+            Some(path) => Config::load(path),
+            None => match user_config_path().filter(|path| path.is_file()) {
+                Some(path) => Config::load(path),
+                None => Ok(Config::default()),
+            },
+        }
+    }
+
+    pub fn with_project_config(&self, dir: &Path) -> Result<Cow<'_, Self>> {
+        match dir
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG))
+            .find(|path| path.is_file())
+        {
+            Some(path) => {
+                let mut config = self.clone();
+                config.merge(Config::parse(&path)?);
+                config.resolve()?;
+                Ok(Cow::Owned(config))
+            }
+            None => Ok(Cow::Borrowed(self)),
+        }
+    }
+
+    pub fn for_buffer(&self, buffile: &str) -> Result<Cow<'_, Self>> {
+        match Path::new(buffile).parent().filter(|dir| dir.is_absolute()) {
+            Some(dir) => self.with_project_config(dir),
+            None => Ok(Cow::Borrowed(self)),
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).map_err(|err| Error::Config(err.to_string()))
+    }
+
+    fn parse(path: &Path) -> Result<Self> {
+        let config = std::fs::read_to_string(path)
+            .map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))?;
+        toml::from_str(&config).map_err(|err| Error::Config(format!("{}: {}", path.display(), err)))
+    }
+
+    fn merge(&mut self, other: Config) {
+        if other.query_dir.is_some() {
+            self.query_dir = other.query_dir;
+        }
+        if other.grammar_dir.is_some() {
+            self.grammar_dir = other.grammar_dir;
+        }
+        self.languages.extend(other.languages);
+        self.highlight.extend(other.highlight);
+        for (filetype, other) in other.filetype {
+            match self.filetype.get_mut(&filetype) {
+                Some(filetype_config) => filetype_config.merge(other),
+                None => {
+                    self.filetype.insert(filetype, other);
+                }
+            }
+        }
+    }
+
+    fn resolve(&mut self) -> Result<()> {
+        let mut resolved = BTreeMap::new();
+        for filetype in self.filetype.keys() {
+            let mut filetype_config = self.resolve_filetype(filetype, &mut Vec::new())?;
+            filetype_config.expand(filetype)?;
+            resolved.insert(filetype.clone(), filetype_config);
+        }
+        self.resolved = resolved;
+        Ok(())
+    }
+
+    fn resolve_filetype<'a>(
+        &'a self,
+        filetype: &'a str,
+        chain: &mut Vec<&'a str>,
+    ) -> Result<FiletypeConfig> {
+        if chain.contains(&filetype) {
+            chain.push(filetype);
+            return Err(Error::Config(format!(
+                "cyclic extends: {}",
+                chain.join(" -> ")
+            )));
+        }
+        let filetype_config = self.filetype.get(filetype).ok_or_else(|| {
+            Error::Config(format!(
+                "{} extends unknown filetype {}",
+                chain.last().unwrap_or(&""),
+                filetype
+            ))
+        })?;
+        match &filetype_config.extends {
+            Some(parent) => {
+                chain.push(filetype);
+                let parent = self.resolve_filetype(parent, chain)?;
+                chain.pop();
+                Ok(parent.inherit(filetype_config.clone()))
+            }
+            None => Ok(filetype_config.clone()),
+        }
+    }
+
+    pub fn query_dir(&self) -> Option<&str> {
+        self.query_dir.as_deref()
+    }
+
+    pub fn grammar_dir(&self) -> Option<PathBuf> {
+        self.grammar_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(default_grammar_dir)
+    }
+
+    pub fn grammar<'a>(&'a self, filetype: &'a str) -> &'a str {
+        self.languages
+            .get(filetype)
+            .map_or(filetype, String::as_str)
+    }
+
+    /// Face of the capture or of its closest parent capture, i.e. `function` for `function.macro`.
+    pub fn face(&self, capture: &str) -> Option<&str> {
+        let mut capture = capture;
+        loop {
+            if let Some(face) = self.highlight.get(capture) {
+                return Some(face);
+            }
+            capture = &capture[..capture.rfind('.')?];
         }
-        Some(config)
     }
 
     pub fn get_filetype_config<'a>(&'a self, filetype: &str) -> &'a FiletypeConfig {
-        self.filetype
+        self.resolved
             .get(filetype)
-            .or_else(|| self.filetype.get("default"))
+            .or_else(|| self.resolved.get("default"))
             .unwrap()
     }
 }
 
 impl FiletypeConfig {
+    fn merge(&mut self, other: FiletypeConfig) {
+        if other.extends.is_some() {
+            self.extends = other.extends;
+        }
+        if other.blacklist.is_some() {
+            self.blacklist = other.blacklist;
+        }
+        if other.whitelist.is_some() {
+            self.whitelist = other.whitelist;
+        }
+        self.group.extend(other.group);
+        if other.injections.is_some() {
+            self.injections = other.injections;
+        }
+    }
+
+    fn inherit(mut self, child: FiletypeConfig) -> FiletypeConfig {
+        self.extends = child.extends;
+        self.blacklist = concat(self.blacklist, child.blacklist);
+        self.whitelist = concat(self.whitelist, child.whitelist);
+        self.group.extend(child.group);
+        if child.injections.is_some() {
+            self.injections = child.injections;
+        }
+        self
+    }
+
+    fn expand(&mut self, filetype: &str) -> Result<()> {
+        let mut group_kinds = BTreeMap::new();
+        for group in self.group.keys() {
+            group_kinds.insert(
+                group.clone(),
+                self.expand_group(filetype, group, &mut Vec::new())?,
+            );
+        }
+        let expand_set = |kinds: &Option<Vec<String>>| -> Result<Option<HashSet<String>>> {
+            match kinds {
+                Some(kinds) => Ok(Some(
+                    self.expand_kinds(filetype, kinds, &mut Vec::new())?
+                        .into_iter()
+                        .collect(),
+                )),
+                None => Ok(None),
+            }
+        };
+        let whitelist_kinds = expand_set(&self.whitelist)?;
+        let blacklist_kinds = expand_set(&self.blacklist)?;
+        self.whitelist_kinds = whitelist_kinds;
+        self.blacklist_kinds = blacklist_kinds;
+        self.group_kinds = group_kinds;
+        Ok(())
+    }
+
+    fn expand_kinds<'a>(
+        &'a self,
+        filetype: &str,
+        kinds: &'a [String],
+        chain: &mut Vec<&'a str>,
+    ) -> Result<Vec<String>> {
+        let mut expanded = Vec::new();
+        for kind in kinds {
+            match kind.strip_prefix('@') {
+                Some(group) => expanded.extend(self.expand_group(filetype, group, chain)?),
+                None => expanded.push(kind.clone()),
+            }
+        }
+        Ok(expanded)
+    }
+
+    fn expand_group<'a>(
+        &'a self,
+        filetype: &str,
+        group: &'a str,
+        chain: &mut Vec<&'a str>,
+    ) -> Result<Vec<String>> {
+        if chain.contains(&group) {
+            chain.push(group);
+            return Err(Error::Config(format!(
+                "{}: cyclic group: @{}",
+                filetype,
+                chain.join(" -> @")
+            )));
+        }
+        let kinds = self
+            .group
+            .get(group)
+            .ok_or_else(|| Error::Config(format!("{}: unknown group @{}", filetype, group)))?;
+        chain.push(group);
+        let kinds = self.expand_kinds(filetype, kinds, chain)?;
+        chain.pop();
+        Ok(kinds)
+    }
+
+    pub fn injections(&self) -> Option<&str> {
+        self.injections.as_deref()
+    }
+
     pub fn is_node_visible(&self, node: Node) -> bool {
         let kind = node.kind();
-        match &self.whitelist {
-            Some(whitelist) => whitelist.iter().any(|x| x == kind),
-            None => match &self.blacklist {
-                Some(blacklist) => !blacklist.iter().any(|x| x == kind),
+        match &self.whitelist_kinds {
+            Some(whitelist) => whitelist.contains(kind),
+            None => match &self.blacklist_kinds {
+                Some(blacklist) => !blacklist.contains(kind),
                 None => true,
             },
         }
     }
 
+    pub fn group(&self, name: &str) -> Option<&[String]> {
+        self.group_kinds.get(name).map(Vec::as_slice)
+    }
+
     pub fn resolve_alias<'a>(&'a self, kind: &str) -> Vec<String> {
-        self.group
-            .get(kind)
+        let group = kind.strip_prefix('@').unwrap_or(kind);
+        self.group_kinds
+            .get(group)
             .cloned()
             .unwrap_or_else(|| vec![kind.to_string()])
     }
 }
+
+fn concat(parent: Option<Vec<String>>, child: Option<Vec<String>>) -> Option<Vec<String>> {
+    match (parent, child) {
+        (Some(mut parent), Some(child)) => {
+            parent.extend(child);
+            Some(parent)
+        }
+        (parent, child) => parent.or(child),
+    }
+}
+
+fn user_config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("kak-tree").join("config.toml"))
+}
+
+fn default_grammar_dir() -> Option<PathBuf> {
+    std::env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share"))
+        })
+        .map(|dir| dir.join("kak-tree").join("grammars"))
+}
@@ -0,0 +1,313 @@
+use crate::config::FiletypeConfig;
+use crate::error::{Error, Result};
+use crate::kakoune::Selection;
+use crate::Op;
+use tree_sitter::Node;
+
+/// Anonymous tokens separating elements unless `separator` group is configured.
+const SEPARATORS: [&str; 3] = [",", ";", "|"];
+
+/// Anonymous token ending a statement, which belongs to the statement when moving delimiters.
+const TERMINATOR: &str = ";";
+
+pub struct Edit {
+    pub start_byte: usize,
+    pub end_byte: usize,
+    pub text: String,
+    pub selection: std::ops::Range<usize>,
+    pub unchanged: Option<std::ops::Range<usize>>,
+    pub reversed: bool,
+    pub main: bool,
+}
+
+impl Edit {
+    pub fn new(start_byte: usize, end_byte: usize, text: String) -> Self {
+        let selection = 0..text.len();
+        Edit {
+            start_byte,
+            end_byte,
+            text,
+            selection,
+            unchanged: None,
+            reversed: false,
+            main: false,
+        }
+    }
+
+    pub fn select(mut self, start: usize, end: usize) -> Self {
+        self.selection = start..end;
+        self
+    }
+
+    /// Select a range of the content outside of the edit instead, i.e. a neighbor of deleted text.
+    pub fn select_unchanged(mut self, start_byte: usize, end_byte: usize) -> Self {
+        self.unchanged = Some(start_byte..end_byte);
+        self
+    }
+
+    /// Keep direction and main flag of the selection the edit is made for.
+    pub fn like(mut self, selection: &Selection) -> Self {
+        self.reversed = selection.reversed;
+        self.main = selection.main;
+        self
+    }
+}
+
+pub fn text<'a>(content: &'a str, node: &Node) -> &'a str {
+    &content[node.start_byte()..node.end_byte()]
+}
+
+pub fn normalize(mut edits: Vec<Edit>) -> Result<Vec<Edit>> {
+    edits.sort_by_key(|edit| (edit.start_byte, edit.end_byte));
+    if edits
+        .windows(2)
+        .any(|pair| pair[1].start_byte < pair[0].end_byte)
+    {
+        return Err(Error::Op("edits of selections overlap".to_string()));
+    }
+    Ok(edits)
+}
+
+pub fn apply(content: &str, edits: &[Edit]) -> (String, Vec<std::ops::Range<usize>>) {
+    let mut new_content = String::with_capacity(content.len());
+    let mut selections = Vec::with_capacity(edits.len());
+    let mut offset = 0;
+    for edit in edits {
+        new_content.push_str(&content[offset..edit.start_byte]);
+        let start = new_content.len();
+        selections.push(start + edit.selection.start..start + edit.selection.end);
+        new_content.push_str(&edit.text);
+        offset = edit.end_byte;
+    }
+    new_content.push_str(&content[offset..]);
+    for (edit, selection) in edits.iter().zip(&mut selections) {
+        if let Some(unchanged) = &edit.unchanged {
+            *selection = shift(edits, unchanged.start)..shift(edits, unchanged.end);
+        }
+    }
+    (new_content, selections)
+}
+
+/// Position of the byte of the old content, which is not inside of edits, in the new content.
+fn shift(edits: &[Edit], byte: usize) -> usize {
+    edits
+        .iter()
+        .filter(|edit| edit.end_byte <= byte)
+        .fold(byte, |byte, edit| {
+            byte + edit.text.len() - (edit.end_byte - edit.start_byte)
+        })
+}
+
+/// Swap two siblings keeping text between them, selecting the node which is moved in the given
+/// direction.
+pub fn swap(first: Node, second: Node, forward: bool, content: &str) -> Edit {
+    let text = [
+        text(content, &second),
+        &content[first.end_byte()..second.start_byte()],
+        text(content, &first),
+    ]
+    .concat();
+    let len = text.len();
+    let edit = Edit::new(first.start_byte(), second.end_byte(), text);
+    if forward {
+        edit.select(len - (first.end_byte() - first.start_byte()), len)
+    } else {
+        edit.select(0, second.end_byte() - second.start_byte())
+    }
+}
+
+/// Delete siblings from the first node to the last one with a separator and whitespace around
+/// them, selecting the neighbor which takes their place.
+pub fn delete_nodes(
+    filetype_config: &FiletypeConfig,
+    first: Node,
+    last: Node,
+    content: &str,
+) -> Edit {
+    let next = last
+        .next_sibling()
+        .filter(|next| is_separator(filetype_config, next));
+    let previous = first
+        .prev_sibling()
+        .filter(|previous| is_separator(filetype_config, previous));
+    let after = next
+        .and_then(|separator| separator.next_sibling())
+        .filter(|after| after.is_named());
+    let before = previous.and_then(|separator| separator.prev_sibling());
+    match (after, before, next) {
+        // Delete up to the next element, which takes the place of the nodes
+        (Some(after), _, _) => Edit::new(first.start_byte(), after.start_byte(), String::new())
+            .select_unchanged(after.start_byte(), after.end_byte()),
+        // Delete from the end of the previous element, keeping a trailing separator
+        (None, Some(before), _) => Edit::new(before.end_byte(), last.end_byte(), String::new())
+            .select_unchanged(before.start_byte(), before.end_byte()),
+        (None, None, Some(separator)) => {
+            delete_lines(content, first.start_byte(), separator.end_byte())
+        }
+        (None, None, None) => {
+            let edit = delete_lines(content, first.start_byte(), last.end_byte());
+            match last
+                .next_named_sibling()
+                .or_else(|| first.prev_named_sibling())
+            {
+                Some(neighbor) => edit.select_unchanged(neighbor.start_byte(), neighbor.end_byte()),
+                None => edit,
+            }
+        }
+    }
+}
+
+/// Whether the sibling follows the node with only separators between them.
+pub fn are_consecutive(filetype_config: &FiletypeConfig, node: Node, sibling: Node) -> bool {
+    let mut next = node.next_sibling();
+    while let Some(token) = next.filter(|token| *token != sibling) {
+        if !is_separator(filetype_config, &token) {
+            return false;
+        }
+        next = token.next_sibling();
+    }
+    next.is_some()
+}
+
+/// Delete the text together with its line when nothing else is on it, or with spaces following
+/// it otherwise.
+fn delete_lines(content: &str, start: usize, end: usize) -> Edit {
+    let line_start = content[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = content[end..]
+        .find('\n')
+        .map_or(content.len(), |i| end + i + 1);
+    if content[line_start..start].trim().is_empty() && content[end..line_end].trim().is_empty() {
+        Edit::new(line_start, line_end, String::new())
+    } else {
+        let spaces = content[end..line_end]
+            .trim_start_matches(&[' ', '\t'][..])
+            .len();
+        Edit::new(start, line_end - spaces, String::new())
+    }
+}
+
+/// Anonymous first and last children of the node, i.e. brackets around its content. Statements
+/// led by a keyword like `let` or ended by a separator like `;` are not delimited.
+pub fn delimiters<'a>(
+    filetype_config: &FiletypeConfig,
+    node: Node<'a>,
+) -> Option<(Node<'a>, Node<'a>)> {
+    let count = node.child_count();
+    if count < 2 {
+        return None;
+    }
+    let (open, close) = (node.child(0)?, node.child(count - 1)?);
+    if open.is_named()
+        || close.is_named()
+        || open.kind().chars().any(char::is_alphanumeric)
+        || is_separator(filetype_config, &close)
+    {
+        None
+    } else {
+        Some((open, close))
+    }
+}
+
+/// Slurp a sibling of the container into it or barf its first or last element out of it. The
+/// edit selects the container.
+pub fn move_delimiter<'a>(
+    filetype_config: &FiletypeConfig,
+    op: &Op,
+    container: Node<'a>,
+    content: &str,
+) -> Option<Edit> {
+    let (open, close) = delimiters(filetype_config, container)?;
+    // Tokens ending an element, i.e. `;` of a statement, move together with it
+    let end_of = |element: Node<'a>| {
+        let mut node = element;
+        while let Some(next) = node
+            .next_sibling()
+            .filter(|next| *next != close && !next.is_named() && next.kind() == TERMINATOR)
+        {
+            node = next;
+        }
+        node.end_byte()
+    };
+    let text = |start: usize, end: usize| &content[start..end];
+    let edit = match op {
+        Op::SlurpForward => {
+            let sibling = container.next_named_sibling()?;
+            let sibling_end = end_of(sibling);
+            let inner_end = close.prev_sibling()?.end_byte();
+            let text = [
+                text(container.start_byte(), inner_end),
+                text(close.end_byte(), sibling_end),
+                text(inner_end, close.end_byte()),
+            ]
+            .concat();
+            let len = text.len();
+            Edit::new(container.start_byte(), sibling_end, text).select(0, len)
+        }
+        Op::SlurpBackward => {
+            let sibling = container.prev_named_sibling()?;
+            let inner_start = open.next_sibling()?.start_byte();
+            let text = [
+                text(open.start_byte(), inner_start),
+                text(sibling.start_byte(), open.start_byte()),
+                text(inner_start, container.end_byte()),
+            ]
+            .concat();
+            let len = text.len();
+            Edit::new(sibling.start_byte(), container.end_byte(), text).select(0, len)
+        }
+        Op::BarfForward => {
+            let element = close.prev_named_sibling()?;
+            let element_end = end_of(element);
+            // Separator before the element moves out together with it, the only element stays
+            let inner_end = end_of(element.prev_named_sibling()?);
+            let text = [
+                text(container.start_byte(), inner_end),
+                text(element_end, close.end_byte()),
+                text(inner_end, element_end),
+            ]
+            .concat();
+            let len = inner_end - container.start_byte() + close.end_byte() - element_end;
+            Edit::new(container.start_byte(), container.end_byte(), text).select(0, len)
+        }
+        Op::BarfBackward => {
+            let element = open.next_named_sibling()?;
+            // Separator after the element moves out together with it, the only element stays
+            let inner_start = element.next_named_sibling()?.start_byte();
+            let text = [
+                text(element.start_byte(), inner_start),
+                text(open.start_byte(), element.start_byte()),
+                text(inner_start, container.end_byte()),
+            ]
+            .concat();
+            let len = text.len();
+            let start = inner_start - element.start_byte();
+            Edit::new(container.start_byte(), container.end_byte(), text).select(start, len)
+        }
+        _ => return None,
+    };
+    Some(edit)
+}
+
+/// Replace the container with its content between delimiters.
+pub fn splice(filetype_config: &FiletypeConfig, container: Node, content: &str) -> Option<Edit> {
+    let (open, close) = delimiters(filetype_config, container)?;
+    let text = match (open.next_sibling(), close.prev_sibling()) {
+        (Some(first), Some(last)) if first != close => {
+            &content[first.start_byte()..last.end_byte()]
+        }
+        _ => "",
+    };
+    Some(Edit::new(
+        container.start_byte(),
+        container.end_byte(),
+        text.to_string(),
+    ))
+}
+
+fn is_separator(filetype_config: &FiletypeConfig, node: &Node) -> bool {
+    !node.is_named()
+        && match filetype_config.group("separator") {
+            Some(kinds) => kinds.iter().any(|kind| kind == node.kind()),
+            None => SEPARATORS.contains(&node.kind()),
+        }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    Request(String),
+    Config(String),
+    UnsupportedFiletype(String),
+    Selection(String),
+    Query(String),
+    Op(String),
+    Server(String),
+}
+
+impl Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Request(_) => 2,
+            Error::Config(_) => 3,
+            Error::UnsupportedFiletype(_) => 4,
+            Error::Selection(_) => 5,
+            Error::Query(_) => 6,
+            Error::Op(_) => 7,
+            Error::Server(_) => 8,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Request(message) => write!(f, "invalid request: {}", message),
+            Error::Config(message) => write!(f, "invalid config: {}", message),
+            Error::UnsupportedFiletype(message) => write!(f, "unsupported filetype: {}", message),
+            Error::Selection(message) => write!(f, "invalid selection: {}", message),
+            Error::Query(message) => write!(f, "invalid query: {}", message),
+            Error::Op(message) => write!(f, "{}", message),
+            Error::Server(message) => write!(f, "server failure: {}", message),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,4 +1,8 @@
-use tree_sitter::Language;
+use crate::config::Config;
+use crate::error::{Error, Result};
+use libloading::{Library, Symbol};
+use std::path::Path;
+use tree_sitter::{Language, LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION};
 
 extern "C" {
     #[cfg(feature = "bash")]
@@ -43,14 +47,16 @@ extern "C" {
     fn tree_sitter_rust() -> Language;
     #[cfg(feature = "scala")]
     fn tree_sitter_scala() -> Language;
+    #[cfg(feature = "tsx")]
+    fn tree_sitter_tsx() -> Language;
     #[cfg(feature = "typescript")]
     fn tree_sitter_typescript() -> Language;
 }
 
-pub fn filetype_to_language(filetype: &str) -> Option<Language> {
-    let sitter = match filetype {
+fn grammar_to_language(grammar: &str) -> Option<Language> {
+    let sitter = match grammar {
         #[cfg(feature = "bash")]
-        "sh" => tree_sitter_bash,
+        "bash" => tree_sitter_bash,
         #[cfg(feature = "c")]
         "c" => tree_sitter_c,
         #[cfg(feature = "c_sharp")]
@@ -91,9 +97,47 @@ pub fn filetype_to_language(filetype: &str) -> Option<Language> {
         "rust" => tree_sitter_rust,
         #[cfg(feature = "scala")]
         "scala" => tree_sitter_scala,
+        #[cfg(feature = "tsx")]
+        "tsx" => tree_sitter_tsx,
         #[cfg(feature = "typescript")]
         "typescript" => tree_sitter_typescript,
         _ => return None,
     };
     Some(unsafe { sitter() })
 }
+
+pub fn load_language(config: &Config, filetype: &str) -> Result<Language> {
+    let grammar = config.grammar(filetype);
+    if let Some(language) = grammar_to_language(grammar) {
+        return Ok(language);
+    }
+    let path = config
+        .grammar_dir()
+        .map(|dir| dir.join(format!("{}.{}", grammar, std::env::consts::DLL_EXTENSION)))
+        .filter(|path| path.is_file())
+        .ok_or_else(|| Error::UnsupportedFiletype(filetype.to_owned()))?;
+    load_grammar(&path, grammar).map_err(|err| {
+        Error::UnsupportedFiletype(format!("{}: {}: {}", filetype, path.display(), err))
+    })
+}
+
+fn load_grammar(path: &Path, grammar: &str) -> std::result::Result<Language, String> {
+    let library = Library::new(path).map_err(|err| err.to_string())?;
+    let symbol = format!("tree_sitter_{}", grammar.replace('-', "_"));
+    let language = unsafe {
+        let sitter: Symbol<unsafe extern "C" fn() -> Language> = library
+            .get(symbol.as_bytes())
+            .map_err(|_| format!("symbol {} not found", symbol))?;
+        sitter()
+    };
+    let version = language.version();
+    if !(MIN_COMPATIBLE_LANGUAGE_VERSION..=LANGUAGE_VERSION).contains(&version) {
+        return Err(format!(
+            "incompatible grammar ABI version {}, supported versions are {} to {}",
+            version, MIN_COMPATIBLE_LANGUAGE_VERSION, LANGUAGE_VERSION
+        ));
+    }
+    // Language points into the library data, so the library is never unloaded.
+    std::mem::forget(library);
+    Ok(language)
+}
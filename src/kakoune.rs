@@ -1,89 +1,395 @@
+use crate::edit::{self, Edit};
+use crate::error::{Error, Result};
+use crate::outline::Symbol;
 use itertools::Itertools;
 use tree_sitter::{Point, Range};
 
-pub fn select_ranges(buffer: &[String], ranges: &[Range]) -> String {
-    if ranges.is_empty() {
+/// Buffer content with an index of line starts. Kakoune coordinates and tree-sitter points both
+/// use byte columns, so conversions never have to look at characters except to find their bounds.
+pub struct Buffer<'a> {
+    content: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> Buffer<'a> {
+    pub fn new(content: &'a str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Buffer {
+            content,
+            line_starts,
+        }
+    }
+
+    pub fn content(&self) -> &'a str {
+        self.content
+    }
+
+    pub fn point(&self, byte: usize) -> Point {
+        let row = self.line_starts.partition_point(|&start| start <= byte) - 1;
+        Point::new(row, byte - self.line_starts[row])
+    }
+
+    pub fn range(&self, start_byte: usize, end_byte: usize) -> Range {
+        Range {
+            start_byte,
+            end_byte,
+            start_point: self.point(start_byte),
+            end_point: self.point(end_byte),
+        }
+    }
+
+    fn char_len_at(&self, byte: usize) -> usize {
+        self.content[byte..]
+            .chars()
+            .next()
+            .map_or(0, char::len_utf8)
+    }
+
+    fn char_start_before(&self, byte: usize) -> usize {
+        self.content[..byte]
+            .chars()
+            .next_back()
+            .map_or(byte, |c| byte - c.len_utf8())
+    }
+
+    fn coords(&self, byte: usize) -> String {
+        let point = self.point(byte);
+        format!("{}.{}", point.row + 1, point.column + 1)
+    }
+
+    fn byte(&self, coords: &str) -> Result<usize> {
+        let invalid = || Error::Selection(coords.to_string());
+        let mut parts = coords.split('.');
+        let (row, column) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(row), Some(column), None) => (row, column),
+            _ => return Err(invalid()),
+        };
+        let row = row.parse::<usize>().map_err(|_| invalid())?;
+        let column = column.parse::<usize>().map_err(|_| invalid())?;
+        if row == 0 || row > self.line_starts.len() || column == 0 {
+            return Err(invalid());
+        }
+        let line_end = self
+            .line_starts
+            .get(row)
+            .copied()
+            .unwrap_or(self.content.len());
+        let byte = self.line_starts[row - 1] + column - 1;
+        if (byte < line_end || byte == self.content.len()) && self.content.is_char_boundary(byte) {
+            Ok(byte)
+        } else {
+            Err(invalid())
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Selection {
+    pub range: Range,
+    pub reversed: bool,
+    pub main: bool,
+}
+
+impl Selection {
+    /// Selection of the range with direction and main flag of this selection.
+    pub fn with_range(&self, range: Range) -> Selection {
+        Selection { range, ..*self }
+    }
+
+    /// Byte offset within the character under the cursor.
+    pub fn cursor(&self) -> usize {
+        if self.reversed {
+            self.range.start_byte
+        } else {
+            self.range
+                .end_byte
+                .saturating_sub(1)
+                .max(self.range.start_byte)
+        }
+    }
+}
+
+pub fn select(buffer: &Buffer, selections: &[Selection]) -> String {
+    if selections.is_empty() {
         "fail no selections remaining".into()
     } else {
-        format!("select {}", ranges_to_selections_desc(&buffer, &ranges))
+        format!("select {}", selections_to_desc(buffer, selections))
+    }
+}
+
+/// Replace ranges and select the results, failing with the message when there are no edits.
+pub fn replace_ranges(buffer: &Buffer, edits: Vec<Edit>, message: &str) -> Result<String> {
+    let edits = edit::normalize(edits)?;
+    if edits.is_empty() {
+        return Ok(fail(message));
+    }
+    let ranges = edits
+        .iter()
+        .map(|edit| buffer.range(edit.start_byte, edit.end_byte))
+        .collect::<Vec<_>>();
+    let (new_content, new_ranges) = edit::apply(buffer.content(), &edits);
+    let new_buffer = Buffer::new(&new_content);
+    let new_selections = edits
+        .iter()
+        .zip(new_ranges)
+        .map(|(edit, range)| Selection {
+            range: new_buffer.range(range.start, range.end),
+            reversed: edit.reversed,
+            main: edit.main,
+        })
+        .collect::<Vec<_>>();
+    let commands = format!(
+        "select {}\nset-register dquote {}\nexecute-keys R\nselect {}",
+        ranges_to_selections_desc(buffer, &ranges),
+        edits.iter().map(|edit| quote(&edit.text)).join(" "),
+        selections_to_desc(&new_buffer, &new_selections)
+    );
+    Ok(format!(
+        "evaluate-commands -save-regs '\"' {}",
+        quote(&commands)
+    ))
+}
+
+pub fn highlight_ranges(buffer: &Buffer, timestamp: usize, highlights: &[(Range, &str)]) -> String {
+    let ranges = highlights
+        .iter()
+        .map(|(range, face)| {
+            let (start, end) = range_to_kak_coords(buffer, range);
+            quote(&format!("{},{}|{}", start, end, face))
+        })
+        .join(" ");
+    format!(
+        "set-option buffer tree_highlight_ranges {} {}",
+        timestamp, ranges
+    )
+}
+
+/// Flag lines and underline ranges of syntax errors, and list their messages one per line.
+pub fn diagnostics(buffer: &Buffer, timestamp: usize, diagnostics: &[(Range, String)]) -> String {
+    let lines = diagnostics
+        .iter()
+        .map(|(range, _)| range.start_point.row + 1)
+        .dedup()
+        .map(|line| quote(&format!("{}|{{Error}}!", line)))
+        .join(" ");
+    let ranges = diagnostics
+        .iter()
+        .map(|(range, _)| {
+            let (start, end) = range_to_kak_coords(buffer, range);
+            quote(&format!("{},{}|TreeError", start, end))
+        })
+        .join(" ");
+    let messages = diagnostics
+        .iter()
+        .map(|(range, message)| format!("{}: {}", buffer.coords(range.start_byte), message))
+        .join("\n");
+    format!(
+        "set-option buffer tree_errors {} {}\nset-option buffer tree_error_ranges {} {}\nset-option buffer tree_error_messages {}",
+        timestamp,
+        lines,
+        timestamp,
+        ranges,
+        quote(&messages)
+    )
+}
+
+/// Fill `*tree-outline*` buffer with symbols, one per line, remembering their selections.
+pub fn outline(buffer: &Buffer, bufname: &str, symbols: &[Symbol]) -> String {
+    if symbols.is_empty() {
+        return "fail no symbols".into();
     }
+    let text = symbols
+        .iter()
+        .map(|symbol| {
+            format!(
+                "{}{} {}\n",
+                "  ".repeat(symbol.depth),
+                symbol.kind,
+                symbol.name
+            )
+        })
+        .collect::<String>();
+    let selections = symbols
+        .iter()
+        .map(|symbol| quote(&ranges_to_selections_desc(buffer, &[symbol.range])))
+        .join(" ");
+    let commands = format!(
+        "edit! -scratch *tree-outline*\nset-register dquote {}\nexecute-keys '%R'\nexecute-keys gg\nset-option buffer tree_outline_buffer {}\nset-option buffer tree_outline_selections {}",
+        quote(&text),
+        quote(bufname),
+        selections
+    );
+    format!("evaluate-commands -save-regs '\"' {}", quote(&commands))
 }
 
-pub fn ranges_to_selections_desc(buffer: &[String], ranges: &[Range]) -> String {
+pub fn fail(message: &str) -> String {
+    format!("fail {}", quote(message))
+}
+
+pub fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+pub fn ranges_to_selections_desc(buffer: &Buffer, ranges: &[Range]) -> String {
     ranges
         .iter()
         .map(|range| {
-            let mut end_row = range.end_point.row;
-            let mut end_column = range.end_point.column;
-            if end_column > 0 {
-                end_column -= 1;
+            let (anchor, cursor) = range_to_kak_coords(buffer, range);
+            format!("{},{}", anchor, cursor)
+        })
+        .join(" ")
+}
+
+/// Kakoune makes the first selection of `select` command the main one.
+pub fn selections_to_desc(buffer: &Buffer, selections: &[Selection]) -> String {
+    let main = selections
+        .iter()
+        .position(|selection| selection.main)
+        .unwrap_or(0);
+    selections[main..]
+        .iter()
+        .chain(&selections[..main])
+        .map(|selection| {
+            let (anchor, cursor) = range_to_kak_coords(buffer, &selection.range);
+            if selection.reversed {
+                format!("{},{}", cursor, anchor)
             } else {
-                end_row -= 1;
-                end_column = 1_000_000;
+                format!("{},{}", anchor, cursor)
             }
-            format!(
-                "{},{}",
-                point_to_kak_coords(buffer, range.start_point),
-                point_to_kak_coords(buffer, Point::new(end_row, end_column))
-            )
         })
         .join(" ")
 }
 
-pub fn selections_desc_to_ranges(buffer: &[String], selections_desc: &str) -> Vec<Range> {
+/// Coordinates of the first and the last character of the range, which end is exclusive.
+fn range_to_kak_coords(buffer: &Buffer, range: &Range) -> (String, String) {
+    let last = buffer
+        .char_start_before(range.end_byte)
+        .max(range.start_byte);
+    (buffer.coords(range.start_byte), buffer.coords(last))
+}
+
+/// Parse selections, `main` is 1-based index of the main selection as in `%val{main_reg_hash}`.
+pub fn selections_desc_to_selections(
+    buffer: &Buffer,
+    selections_desc: &str,
+    main: usize,
+) -> Result<Vec<Selection>> {
     selections_desc
         .split_whitespace()
-        .map(|selection_desc| selection_desc_to_range(buffer, selection_desc))
+        .enumerate()
+        .map(|(index, selection_desc)| {
+            let (range, reversed) = selection_desc_to_range(buffer, selection_desc)?;
+            Ok(Selection {
+                range,
+                reversed,
+                main: index + 1 == main.max(1),
+            })
+        })
         .collect()
 }
 
-fn selection_desc_to_range(buffer: &[String], selection_desc: &str) -> Range {
-    let mut range = selection_desc.split(',');
-    let start = range.next().unwrap();
-    let end = range.next().unwrap();
-    let (start_byte, start_point) = kak_coords_to_byte_and_point(buffer, start);
-    let (end_byte, end_point) = kak_coords_to_byte_and_point(buffer, end);
-    let reverse = start_byte > end_byte;
-    if reverse {
-        Range {
-            start_byte: end_byte,
-            end_byte: start_byte,
-            start_point: end_point,
-            end_point: start_point,
-        }
+/// Range of the selection with exclusive end, as tree-sitter ranges are.
+fn selection_desc_to_range(buffer: &Buffer, selection_desc: &str) -> Result<(Range, bool)> {
+    let mut coords = selection_desc.split(',');
+    let (anchor, cursor) = match (coords.next(), coords.next(), coords.next()) {
+        (Some(anchor), Some(cursor), None) => (buffer.byte(anchor)?, buffer.byte(cursor)?),
+        _ => return Err(Error::Selection(selection_desc.to_string())),
+    };
+    let reversed = anchor > cursor;
+    let (first, last) = if reversed {
+        (cursor, anchor)
     } else {
-        Range {
-            start_byte,
-            end_byte,
-            start_point,
-            end_point,
-        }
-    }
+        (anchor, cursor)
+    };
+    let range = buffer.range(first, last + buffer.char_len_at(last));
+    Ok((range, reversed))
 }
 
-fn point_to_kak_coords(buffer: &[String], p: Point) -> String {
-    let offset = buffer[p.row]
-        .char_indices()
-        .enumerate()
-        .find_map(|(column, (offset, _))| {
-            if column == p.column {
-                Some(offset)
-            } else {
-                None
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::byte_to_point;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    const CONTENT: &str = "[aé日🦀 \r\n]{0,64}";
+
+    fn char_starts(content: &str) -> Vec<usize> {
+        content.char_indices().map(|(i, _)| i).collect()
+    }
+
+    proptest! {
+        #[test]
+        fn point_uses_byte_columns(content in CONTENT, index: Index) {
+            let buffer = Buffer::new(&content);
+            let byte = *index.get(&[char_starts(&content), vec![content.len()]].concat());
+            prop_assert_eq!(buffer.point(byte), byte_to_point(content.as_bytes(), byte));
+        }
+
+        #[test]
+        fn coords_round_trip(content in CONTENT, index: Index) {
+            prop_assume!(!content.is_empty());
+            let buffer = Buffer::new(&content);
+            let byte = *index.get(&char_starts(&content));
+            prop_assert_eq!(buffer.byte(&buffer.coords(byte)).unwrap(), byte);
+        }
+
+        #[test]
+        fn coords_inside_character_are_invalid(content in CONTENT) {
+            let buffer = Buffer::new(&content);
+            for byte in (0..content.len()).filter(|&byte| !content.is_char_boundary(byte)) {
+                let point = buffer.point(byte);
+                let coords = format!("{}.{}", point.row + 1, point.column + 1);
+                prop_assert!(buffer.byte(&coords).is_err());
             }
-        })
-        .unwrap_or_else(|| buffer[p.row].len());
-    format!("{}.{}", p.row + 1, offset + 1)
-}
-
-fn kak_coords_to_byte_and_point(buffer: &[String], coords: &str) -> (usize, Point) {
-    let mut coords = coords.split('.');
-    let row = coords.next().unwrap().parse::<usize>().unwrap() - 1;
-    let offset = coords.next().unwrap().parse::<usize>().unwrap() - 1;
-    let byte = buffer[..row].iter().fold(0, |offset, c| offset + c.len()) + offset;
-    let column = buffer[row]
-        .char_indices()
-        .position(|(i, _)| i == offset)
-        .unwrap();
-    (byte, Point::new(row, column))
+        }
+
+        #[test]
+        fn selection_round_trip(content in CONTENT, anchor: Index, cursor: Index) {
+            prop_assume!(!content.is_empty());
+            let buffer = Buffer::new(&content);
+            let starts = char_starts(&content);
+            let (anchor, cursor) = (*anchor.get(&starts), *cursor.get(&starts));
+            let desc = format!("{},{}", buffer.coords(anchor), buffer.coords(cursor));
+            let selections = selections_desc_to_selections(&buffer, &desc, 1).unwrap();
+            let range = selections[0].range;
+            let last = anchor.max(cursor);
+            prop_assert_eq!(range.start_byte, anchor.min(cursor));
+            prop_assert_eq!(range.end_byte, last + buffer.char_len_at(last));
+            prop_assert_eq!(selections[0].reversed, anchor > cursor);
+            prop_assert_eq!(selections_to_desc(&buffer, &selections), desc);
+        }
+    }
+
+    #[test]
+    fn empty_lines() {
+        let buffer = Buffer::new("a\n\n\nb\n");
+        assert_eq!(buffer.byte("2.1").unwrap(), 2);
+        assert_eq!(buffer.byte("3.1").unwrap(), 3);
+        assert_eq!(buffer.byte("4.1").unwrap(), 4);
+        assert!(buffer.byte("2.2").is_err());
+        assert_eq!(buffer.coords(3), "3.1");
+    }
+
+    #[test]
+    fn crlf() {
+        let buffer = Buffer::new("ab\r\ncd\r\n");
+        assert_eq!(buffer.byte("1.3").unwrap(), 2);
+        assert_eq!(buffer.byte("1.4").unwrap(), 3);
+        assert_eq!(buffer.byte("2.1").unwrap(), 4);
+        assert!(buffer.byte("1.5").is_err());
+        assert_eq!(buffer.coords(5), "2.2");
+    }
+
+    #[test]
+    fn multibyte_selection() {
+        let buffer = Buffer::new("x = \"日本\";\n");
+        let selections = selections_desc_to_selections(&buffer, "1.9,1.6", 1).unwrap();
+        assert_eq!(selections[0].range.start_byte, 5);
+        assert_eq!(selections[0].range.end_byte, 11);
+        assert_eq!(selections_to_desc(&buffer, &selections), "1.9,1.6");
+        assert!(selections_desc_to_selections(&buffer, "1.7,1.7", 1).is_err());
+    }
 }
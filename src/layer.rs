@@ -0,0 +1,160 @@
+use crate::config::Config;
+use crate::error::Result;
+use crate::{ffi, query};
+use slog_scope::debug;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use tree_sitter::{InputEdit, Parser, Query, QueryCursor, Range, Tree};
+
+const MAX_DEPTH: usize = 4;
+
+/// Compiled injection queries by filetype and source.
+pub type Queries = HashMap<(String, String), Query>;
+
+pub struct Layer {
+    pub filetype: String,
+    pub tree: Tree,
+    pub range: Range,
+    pub parent: Option<usize>,
+    depth: usize,
+}
+
+/// Parse languages injected into the buffer. The first layer is the buffer itself, every injected
+/// layer comes after the layer it is injected into. Injected layers of `old_layers` are reused for
+/// reparsing when their trees are edited to match the content.
+pub fn parse(
+    config: &Config,
+    filetype: &str,
+    tree: Tree,
+    content: &str,
+    old_layers: &[Layer],
+    queries: &mut Queries,
+) -> Result<Vec<Layer>> {
+    let mut layers = vec![Layer {
+        filetype: filetype.to_string(),
+        range: tree.root_node().range(),
+        tree,
+        parent: None,
+        depth: 0,
+    }];
+    let mut reused = vec![false; old_layers.len()];
+    let mut index = 0;
+    while index < layers.len() {
+        let depth = layers[index].depth + 1;
+        if depth <= MAX_DEPTH {
+            for (filetype, range) in injections(config, &layers[index], content, queries)? {
+                let old_index = old_layers.iter().enumerate().position(|(i, old)| {
+                    !reused[i] && old.depth == depth && old.filetype == filetype && {
+                        let old_range = old.tree.root_node().range();
+                        old_range.start_byte <= range.end_byte
+                            && range.start_byte <= old_range.end_byte
+                    }
+                });
+                let old_tree = old_index.map(|i| {
+                    reused[i] = true;
+                    &old_layers[i].tree
+                });
+                match parse_injection(config, &filetype, range, content, old_tree) {
+                    Some(tree) => layers.push(Layer {
+                        filetype,
+                        tree,
+                        range,
+                        parent: Some(index),
+                        depth,
+                    }),
+                    None => debug!("failed to parse {} injection", filetype),
+                }
+            }
+        }
+        index += 1;
+    }
+    Ok(layers)
+}
+
+/// Apply the edit to trees of all layers, so they could be reused for reparsing.
+pub fn edit(layers: &mut [Layer], edit: &InputEdit) {
+    for layer in layers {
+        layer.tree.edit(edit);
+    }
+}
+
+/// Index of the deepest layer containing the range.
+pub fn innermost(layers: &[Layer], range: &Range) -> usize {
+    layers
+        .iter()
+        .rposition(|layer| {
+            layer.parent.is_some()
+                && layer.range.start_byte <= range.start_byte
+                && range.end_byte <= layer.range.end_byte
+        })
+        .unwrap_or(0)
+}
+
+fn injections(
+    config: &Config,
+    layer: &Layer,
+    content: &str,
+    queries: &mut Queries,
+) -> Result<Vec<(String, Range)>> {
+    let source = match config.get_filetype_config(&layer.filetype).injections() {
+        Some(source) => Cow::Borrowed(source),
+        None => match query::load(config, &layer.filetype, "injections") {
+            Some(source) => source,
+            None => return Ok(Vec::new()),
+        },
+    };
+    let key = (layer.filetype.clone(), source.into_owned());
+    if !queries.contains_key(&key) {
+        let query = query::compile(layer.tree.language(), &key.1)?;
+        queries.insert(key.clone(), query);
+    }
+    let query = &queries[&key];
+    let content_capture = match query::capture_index(query, "injection.content") {
+        Some(capture) => capture,
+        None => return Ok(Vec::new()),
+    };
+    let language_capture = query::capture_index(query, "injection.language");
+    let text = content.as_bytes();
+    let mut cursor = QueryCursor::new();
+    let mut injections = Vec::new();
+    for query_match in cursor.matches(query, layer.tree.root_node(), |node| {
+        &text[node.byte_range()]
+    }) {
+        let language = query_match
+            .captures
+            .iter()
+            .find(|c| Some(c.index) == language_capture)
+            .map(|c| content[c.node.byte_range()].trim().to_string())
+            .or_else(|| {
+                query
+                    .property_settings(query_match.pattern_index)
+                    .iter()
+                    .find(|property| &*property.key == "injection.language")
+                    .and_then(|property| property.value.as_ref())
+                    .map(|value| value.to_string())
+            });
+        let range = query_match
+            .captures
+            .iter()
+            .find(|c| c.index == content_capture)
+            .map(|c| c.node.range());
+        if let (Some(language), Some(range)) = (language, range) {
+            injections.push((language, range));
+        }
+    }
+    Ok(injections)
+}
+
+fn parse_injection(
+    config: &Config,
+    filetype: &str,
+    range: Range,
+    content: &str,
+    old_tree: Option<&Tree>,
+) -> Option<Tree> {
+    let language = ffi::load_language(config, filetype).ok()?;
+    let mut parser = Parser::new();
+    parser.set_language(language).ok()?;
+    parser.set_included_ranges(&[range]).ok()?;
+    parser.parse(content, old_tree)
+}
@@ -2,7 +2,7 @@ use sloggers::terminal::{Destination, TerminalLoggerBuilder};
 use sloggers::types::Severity;
 use sloggers::Build;
 
-pub fn init_global_logger(verbosity: u8) {
+pub fn init_global_logger(verbosity: u8) -> slog_scope::GlobalLoggerGuard {
     let level = match verbosity {
         0 => Severity::Error,
         1 => Severity::Warning,
@@ -15,5 +15,5 @@ pub fn init_global_logger(verbosity: u8) {
     builder.level(level);
     builder.destination(Destination::Stderr);
     let logger = builder.build().unwrap();
-    let _guard = slog_scope::set_global_logger(logger);
+    slog_scope::set_global_logger(logger)
 }
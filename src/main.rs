@@ -1,56 +1,173 @@
 use crate::config::{Config, FiletypeConfig};
+use crate::edit::Edit;
+use crate::error::{Error, Result};
+use crate::layer::Layer;
 use clap::{crate_version, App, Arg};
 use serde::Deserialize;
+use slog_scope::error;
+use std::collections::HashMap;
 use std::io::Read;
 use toml;
-use tree_sitter::{Node, Parser, Range};
+use tree_sitter::{Node, Parser, Range, Tree};
 
 mod config;
+mod edit;
+mod error;
 mod ffi;
 mod kakoune;
+mod layer;
 mod log;
+mod outline;
+mod query;
+mod server;
 mod tree;
 
+/// Characters of unexpected text quoted in syntax error messages.
+const ERROR_TEXT_LENGTH: usize = 40;
+
 #[derive(Deserialize)]
 struct Request {
     op: Op,
     filetype: String,
+    #[serde(default)]
+    buffer: String,
+    #[serde(default)]
+    buffile: String,
+    #[serde(default)]
+    timestamp: usize,
     selections_desc: String,
+    #[serde(default)]
+    main_selection: usize,
     content: String,
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 enum Op {
+    BarfBackward,
+    BarfForward,
+    Breadcrumb,
+    CloseBuffer,
+    DeleteNode,
+    Diagnostics,
+    Highlight,
     NodeSExp,
-    SelectChildren { kind: Option<String> },
-    SelectNextNode { kind: Option<String> },
-    SelectParentNode { kind: Option<String> },
-    SelectPreviousNode { kind: Option<String> },
+    Outline,
+    RaiseNode {
+        kind: Option<String>,
+    },
+    SelectChildren {
+        kind: Option<String>,
+    },
+    SelectNextError {
+        #[serde(default)]
+        wrap: bool,
+    },
+    SelectNextNode {
+        kind: Option<String>,
+        #[serde(default)]
+        count: usize,
+    },
+    SelectParentNode {
+        kind: Option<String>,
+        #[serde(default)]
+        count: usize,
+    },
+    SelectPreviousError {
+        #[serde(default)]
+        wrap: bool,
+    },
+    SelectPreviousNode {
+        kind: Option<String>,
+        #[serde(default)]
+        count: usize,
+    },
+    SelectQuery {
+        query: String,
+        capture: Option<String>,
+    },
+    SelectTextObject {
+        name: String,
+        inner: bool,
+    },
+    ShrinkSelection,
+    SlurpBackward,
+    SlurpForward,
+    SpliceNode,
+    SwapNextNode,
+    SwapPreviousNode,
 }
 
 fn main() {
     let matches = cli();
 
     let verbosity = matches.occurrences_of("v") as u8;
-    log::init_global_logger(verbosity);
+    let _guard = log::init_global_logger(verbosity);
+
+    let config =
+        Config::load_user(matches.value_of("config")).unwrap_or_else(|err| exit_with_error(err));
 
     if let Some(filetype) = matches.value_of("do-you-understand") {
-        let language = ffi::filetype_to_language(filetype);
-        std::process::exit(if language.is_some() { 0 } else { 1 });
+        let language = ffi::load_language(&config, filetype);
+        std::process::exit(if language.is_ok() { 0 } else { 1 });
     }
 
-    let config = if let Some(config_path) = matches.value_of("config") {
-        Config::load(config_path).unwrap()
-    } else {
-        Config::default()
-    };
+    if matches.is_present("print-config") {
+        let config = std::env::current_dir()
+            .map_err(|err| Error::Config(err.to_string()))
+            .and_then(|dir| config.with_project_config(&dir)?.to_toml());
+        match config {
+            Ok(config) => print!("{}", config),
+            Err(err) => exit_with_error(err),
+        }
+        return;
+    }
+
+    if let Some(socket) = matches.value_of("server") {
+        if let Err(err) = server::run(&config, socket) {
+            exit_with_error(err);
+        }
+        return;
+    }
 
     let mut request = String::new();
-    std::io::stdin().read_to_string(&mut request).unwrap();
-    let request: Request = toml::from_str(&request).unwrap();
-    let response = handle_request(&config, &request);
-    println!("{}", response);
+    if let Err(err) = std::io::stdin().read_to_string(&mut request) {
+        exit_with_error(Error::Request(err.to_string()));
+    }
+    if let Some((code, response)) = matches
+        .value_of("connect")
+        .and_then(|socket| server::connect(socket, &request))
+    {
+        println!("{}", response);
+        std::process::exit(code);
+    }
+    let response = parse_request(&request).and_then(|request| {
+        // Nothing is kept between requests without a server
+        if let Op::CloseBuffer = request.op {
+            return Ok(String::new());
+        }
+        let config = config.for_buffer(&request.buffile)?;
+        let tree = parse(&mut Parser::new(), &config, &request, None)?;
+        let layers = layer::parse(
+            &config,
+            &request.filetype,
+            tree,
+            &request.content,
+            &[],
+            &mut layer::Queries::new(),
+        )?;
+        handle_request(&config, &request, &layers)
+    });
+    match response {
+        Ok(response) => println!("{}", response),
+        Err(err) => exit_with_error(err),
+    }
+}
+
+fn exit_with_error(err: Error) -> ! {
+    error!("{}", err);
+    println!("{}", kakoune::fail(&err.to_string()));
+    std::process::exit(err.exit_code());
 }
 
 fn cli() -> clap::ArgMatches<'static> {
@@ -66,6 +183,25 @@ fn cli() -> clap::ArgMatches<'static> {
                 .help("Read config from FILE")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Print effective config for the current directory and exit"),
+        )
+        .arg(
+            Arg::with_name("server")
+                .long("server")
+                .value_name("SOCKET")
+                .help("Serve requests on SOCKET keeping parsed buffers between them")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("connect")
+                .long("connect")
+                .value_name("SOCKET")
+                .help("Forward request to server on SOCKET, handle it locally if unreachable")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("do-you-understand")
                 .long("do-you-understand")
@@ -82,97 +218,486 @@ fn cli() -> clap::ArgMatches<'static> {
         .get_matches()
 }
 
-fn handle_request(config: &Config, request: &Request) -> String {
-    let mut parser = Parser::new();
-    let language = ffi::filetype_to_language(&request.filetype).unwrap();
-    parser.set_language(language).unwrap();
-    let tree = parser.parse(&request.content, None).unwrap();
-    let buffer = request
-        .content
-        .split('\n')
-        .map(|s| format!("{}\n", s))
-        .collect::<Vec<_>>();
-    let ranges = kakoune::selections_desc_to_ranges(&buffer, &request.selections_desc);
-    let mut new_ranges = Vec::new();
-    let filetype_config = config.get_filetype_config(&request.filetype);
-    match &request.op {
-        Op::SelectParentNode { kind } => {
-            let kinds = kind
-                .as_ref()
-                .and_then(|kind| Some(filetype_config.resolve_alias(kind)));
-            for range in &ranges {
-                let node = tree::shrink_to_range(tree.root_node(), range);
-                let node = find_parent_of_interest(filetype_config, node, &kinds);
-                new_ranges.push(node.range());
-            }
-            kakoune::select_ranges(&buffer, &new_ranges)
-        }
-        Op::SelectNextNode { kind } => {
-            let kinds = kind
-                .as_ref()
-                .and_then(|kind| Some(filetype_config.resolve_alias(kind)));
-            'outer_next: for range in &ranges {
-                let node = tree::shrink_to_range(tree.root_node(), range);
-                let mut cursor = traverse_up_to_node_which_matters(filetype_config, node);
-                while let Some(node) = cursor.next_named_sibling() {
-                    if filetype_config.is_node_visible(node) && node_of_kinds(node, &kinds) {
-                        new_ranges.push(node.range());
-                        continue 'outer_next;
+fn parse_request(request: &str) -> Result<Request> {
+    toml::from_str(request).map_err(|err| Error::Request(err.to_string()))
+}
+
+fn parse(
+    parser: &mut Parser,
+    config: &Config,
+    request: &Request,
+    old_tree: Option<&Tree>,
+) -> Result<Tree> {
+    let language = ffi::load_language(config, &request.filetype)?;
+    parser
+        .set_language(language)
+        .map_err(|err| Error::UnsupportedFiletype(format!("{}: {}", request.filetype, err)))?;
+    parser
+        .parse(&request.content, old_tree)
+        .ok_or_else(|| Error::Op(format!("failed to parse {}", request.buffer)))
+}
+
+fn handle_request(config: &Config, request: &Request, layers: &[Layer]) -> Result<String> {
+    let buffer = kakoune::Buffer::new(&request.content);
+    let selections = kakoune::selections_desc_to_selections(
+        &buffer,
+        &request.selections_desc,
+        request.main_selection,
+    )?;
+    let mut new_selections = Vec::new();
+    let response = match &request.op {
+        Op::SelectParentNode { kind, count } => {
+            for selection in &selections {
+                let range = &selection.range;
+                let mut index = layer::innermost(layers, range);
+                let mut range = *range;
+                for _ in 0..(*count).max(1) {
+                    loop {
+                        let layer = &layers[index];
+                        let filetype_config = config.get_filetype_config(&layer.filetype);
+                        let kinds = kind
+                            .as_ref()
+                            .map(|kind| filetype_config.resolve_alias(kind));
+                        let node = tree::shrink_to_range(layer.tree.root_node(), &range);
+                        let parent = find_parent_of_interest(filetype_config, node, &kinds);
+                        match layer.parent {
+                            // Step out to the host language at the top of the injected one
+                            Some(host) if parent.range() == node.range() => index = host,
+                            _ => {
+                                range = parent.range();
+                                break;
+                            }
+                        }
                     }
-                    cursor = node;
-                }
-                let node = find_parent_of_interest(filetype_config, node, &kinds);
-                new_ranges.push(node.range());
-            }
-            kakoune::select_ranges(&buffer, &new_ranges)
-        }
-        Op::SelectPreviousNode { kind } => {
-            let kinds = kind
-                .as_ref()
-                .and_then(|kind| Some(filetype_config.resolve_alias(kind)));
-            'outer_prev: for range in &ranges {
-                let node = tree::shrink_to_range(tree.root_node(), range);
-                let mut cursor = traverse_up_to_node_which_matters(filetype_config, node);
-                while let Some(node) = cursor.prev_named_sibling() {
-                    if filetype_config.is_node_visible(node) && node_of_kinds(node, &kinds) {
-                        new_ranges.push(node.range());
-                        continue 'outer_prev;
+                }
+                new_selections.push(selection.with_range(range));
+            }
+            kakoune::select(&buffer, &new_selections)
+        }
+        Op::SelectNextNode { kind, count } | Op::SelectPreviousNode { kind, count } => {
+            let forward = matches!(request.op, Op::SelectNextNode { .. });
+            for selection in &selections {
+                let (filetype_config, mut node) = node_at(config, layers, &selection.range);
+                let kinds = kind
+                    .as_ref()
+                    .map(|kind| filetype_config.resolve_alias(kind));
+                for _ in 0..(*count).max(1) {
+                    match find_sibling_of_interest(filetype_config, node, &kinds, forward) {
+                        Some(sibling) => node = sibling,
+                        None => break,
                     }
-                    cursor = node;
                 }
-                let node = find_parent_of_interest(filetype_config, node, &kinds);
-                new_ranges.push(node.range());
+                let node = traverse_up_to_node_which_matters(filetype_config, node);
+                new_selections.push(selection.with_range(node.range()));
+            }
+            kakoune::select(&buffer, &new_selections)
+        }
+        Op::SelectNextError { wrap } | Op::SelectPreviousError { wrap } => {
+            let forward = matches!(request.op, Op::SelectNextError { .. });
+            let mut errors = layers
+                .iter()
+                .flat_map(|layer| tree::errors(layer.tree.root_node()))
+                .map(|node| node.range())
+                .collect::<Vec<_>>();
+            if errors.is_empty() {
+                return Ok(kakoune::fail("no syntax errors"));
+            }
+            errors.sort_by_key(|range| range.start_byte);
+            for selection in &selections {
+                let start = selection.range.start_byte;
+                let error = if forward {
+                    errors
+                        .iter()
+                        .find(|error| error.start_byte > start)
+                        .or_else(|| errors.first().filter(|_| *wrap))
+                } else {
+                    errors
+                        .iter()
+                        .rev()
+                        .find(|error| error.start_byte < start)
+                        .or_else(|| errors.last().filter(|_| *wrap))
+                };
+                new_selections.push(selection.with_range(*error.unwrap_or(&selection.range)));
             }
-            kakoune::select_ranges(&buffer, &new_ranges)
+            kakoune::select(&buffer, &new_selections)
         }
         Op::SelectChildren { kind } => {
-            match kind {
-                Some(kind) => {
-                    let kinds = filetype_config.resolve_alias(kind);
-                    for range in &ranges {
-                        for node in tree::nodes_in_range(tree.root_node(), range) {
-                            select_nodes(&node, &kinds, &mut new_ranges);
+            for selection in &selections {
+                let range = &selection.range;
+                let layer = &layers[layer::innermost(layers, range)];
+                let filetype_config = config.get_filetype_config(&layer.filetype);
+                match kind {
+                    Some(kind) => {
+                        let kinds = filetype_config.resolve_alias(kind);
+                        let mut ranges = Vec::new();
+                        for node in tree::nodes_in_range(layer.tree.root_node(), range) {
+                            select_nodes(&node, &kinds, &mut ranges);
                         }
+                        new_selections
+                            .extend(ranges.into_iter().map(|range| selection.with_range(range)));
                     }
-                }
-                None => {
-                    for range in &ranges {
-                        let node = tree::shrink_to_range(tree.root_node(), range);
+                    None => {
+                        let node = tree::shrink_to_range(layer.tree.root_node(), range);
                         for child in tree::named_children(&node) {
                             if filetype_config.is_node_visible(child) {
-                                new_ranges.push(child.range());
+                                new_selections.push(selection.with_range(child.range()));
                             }
                         }
                     }
                 }
             }
-            kakoune::select_ranges(&buffer, &new_ranges)
+            kakoune::select(&buffer, &new_selections)
+        }
+        Op::SelectQuery { query, capture } => {
+            let indices = innermost_layers(layers, &selections);
+            let queries = per_filetype(layers, &indices, |layer| {
+                let query = query::compile(layer.tree.language(), query)?;
+                let capture = match capture {
+                    Some(capture) => Some(
+                        query::capture_index(&query, capture)
+                            .ok_or_else(|| Error::Query(format!("no capture named {}", capture)))?,
+                    ),
+                    None => None,
+                };
+                Ok((query, capture))
+            })?;
+            for (selection, &index) in selections.iter().zip(&indices) {
+                let layer = &layers[index];
+                let (query, capture) = &queries[&*layer.filetype];
+                let ranges = query::captures_in_range(
+                    query,
+                    layer.tree.root_node(),
+                    &request.content,
+                    &selection.range,
+                    *capture,
+                );
+                new_selections.extend(ranges.into_iter().map(|range| selection.with_range(range)));
+            }
+            new_selections
+                .sort_by_key(|selection| (selection.range.start_byte, selection.range.end_byte));
+            new_selections.dedup_by(|duplicate, selection| {
+                if duplicate.range == selection.range {
+                    selection.main |= duplicate.main;
+                    true
+                } else {
+                    false
+                }
+            });
+            kakoune::select(&buffer, &new_selections)
+        }
+        Op::SelectTextObject { name, inner } => {
+            let capture = format!("{}.{}", name, if *inner { "inner" } else { "outer" });
+            let indices = innermost_layers(layers, &selections);
+            let queries = per_filetype(layers, &indices, |layer| {
+                let source =
+                    query::load(config, &layer.filetype, "textobjects").ok_or_else(|| {
+                        Error::Op(format!(
+                            "no textobjects query for filetype {}",
+                            layer.filetype
+                        ))
+                    })?;
+                let query = query::compile(layer.tree.language(), &source)?;
+                let index = query::capture_index(&query, &capture)
+                    .ok_or_else(|| Error::Op(format!("no textobject named {}", capture)))?;
+                Ok((query, index))
+            })?;
+            for (selection, &index) in selections.iter().zip(&indices) {
+                let layer = &layers[index];
+                let (query, capture) = &queries[&*layer.filetype];
+                if let Some(range) = query::smallest_capture_containing_range(
+                    query,
+                    layer.tree.root_node(),
+                    &request.content,
+                    &selection.range,
+                    *capture,
+                ) {
+                    new_selections.push(selection.with_range(range));
+                }
+            }
+            kakoune::select(&buffer, &new_selections)
+        }
+        Op::ShrinkSelection => {
+            for selection in &selections {
+                let (filetype_config, node) = node_at(config, layers, &selection.range);
+                let range = find_child_of_interest(filetype_config, node, selection)
+                    .map_or(selection.range, |child| child.range());
+                new_selections.push(selection.with_range(range));
+            }
+            kakoune::select(&buffer, &new_selections)
+        }
+        Op::SwapNextNode
+        | Op::SwapPreviousNode
+        | Op::DeleteNode
+        | Op::SlurpForward
+        | Op::SlurpBackward
+        | Op::BarfForward
+        | Op::BarfBackward
+        | Op::SpliceNode
+        | Op::RaiseNode { .. } => {
+            let (edits, message) = make_edits(config, request, layers, &selections)?;
+            kakoune::replace_ranges(&buffer, edits, message)?
+        }
+        Op::CloseBuffer => String::new(),
+        Op::Outline => {
+            let symbols =
+                outline::symbols(config, &request.filetype, &layers[0].tree, &request.content)?;
+            kakoune::outline(&buffer, &request.buffer, &symbols)
+        }
+        Op::Breadcrumb => {
+            let selection = selections
+                .iter()
+                .find(|selection| selection.main)
+                .ok_or_else(|| Error::Selection("no selections".to_string()))?;
+            let mut labels = Vec::new();
+            let mut range = selection.range;
+            let mut index = Some(layer::innermost(layers, &range));
+            while let Some(current) = index {
+                let layer = &layers[current];
+                let filetype_config = config.get_filetype_config(&layer.filetype);
+                let kinds = filetype_config.group("breadcrumb");
+                let mut cursor = Some(tree::shrink_to_range(layer.tree.root_node(), &range));
+                while let Some(node) = cursor {
+                    let visible = match kinds {
+                        Some(kinds) => kinds.iter().any(|kind| kind == node.kind()),
+                        None => {
+                            node.named_child_count() > 0 && filetype_config.is_node_visible(node)
+                        }
+                    };
+                    if visible && node.parent().is_some() {
+                        labels.push(breadcrumb_label(node, &request.content));
+                    }
+                    cursor = node.parent();
+                }
+                range = layer.range;
+                index = layer.parent;
+            }
+            labels.reverse();
+            format!(
+                "set-option window tree_breadcrumb {}",
+                kakoune::quote(&labels.join(" > "))
+            )
+        }
+        Op::Diagnostics => {
+            let mut diagnostics = Vec::new();
+            for layer in layers {
+                for node in tree::errors(layer.tree.root_node()) {
+                    diagnostics.push((node.range(), error_message(node, &request.content)));
+                }
+            }
+            diagnostics.sort_by_key(|(range, _)| range.start_byte);
+            kakoune::diagnostics(&buffer, request.timestamp, &diagnostics)
+        }
+        Op::Highlight => {
+            let indices = (0..layers.len()).collect::<Vec<_>>();
+            let queries = per_filetype(layers, &indices, |layer| {
+                match query::load(config, &layer.filetype, "highlights") {
+                    Some(source) => {
+                        let query = query::compile(layer.tree.language(), &source)?;
+                        let faces = query
+                            .capture_names()
+                            .iter()
+                            .map(|capture| config.face(capture))
+                            .collect::<Vec<_>>();
+                        Ok(Some((query, faces)))
+                    }
+                    None => Ok(None),
+                }
+            })?;
+            let mut highlights = Vec::new();
+            for layer in layers {
+                if let Some((query, faces)) = &queries[&*layer.filetype] {
+                    highlights.extend(query::highlights(
+                        query,
+                        layer.tree.root_node(),
+                        &request.content,
+                        faces,
+                    ));
+                }
+            }
+            kakoune::highlight_ranges(&buffer, request.timestamp, &highlights)
         }
         Op::NodeSExp => {
-            let node = tree::shrink_to_range(tree.root_node(), &ranges[0]);
-            format!("info '{}'", node.to_sexp())
+            let range = &selections
+                .iter()
+                .find(|selection| selection.main)
+                .ok_or_else(|| Error::Selection("no selections".to_string()))?
+                .range;
+            let (_, node) = node_at(config, layers, range);
+            format!("info {}", kakoune::quote(&node.to_sexp()))
+        }
+    };
+    Ok(response)
+}
+
+/// Edits of an editing operation and a message to fail with when there are none.
+fn make_edits(
+    config: &Config,
+    request: &Request,
+    layers: &[Layer],
+    selections: &[kakoune::Selection],
+) -> Result<(Vec<Edit>, &'static str)> {
+    let mut edits = Vec::new();
+    let message = match &request.op {
+        Op::SwapNextNode | Op::SwapPreviousNode => {
+            let forward = matches!(request.op, Op::SwapNextNode);
+            for selection in selections {
+                let (filetype_config, node) = visible_node_at(config, layers, &selection.range);
+                if let Some(sibling) =
+                    find_sibling_of_interest(filetype_config, node, &None, forward)
+                {
+                    let (first, second) = if forward {
+                        (node, sibling)
+                    } else {
+                        (sibling, node)
+                    };
+                    let edit = edit::swap(first, second, forward, &request.content);
+                    edits.push(edit.like(selection));
+                }
+            }
+            "no sibling to swap with"
+        }
+        Op::DeleteNode => {
+            let mut nodes = Vec::new();
+            for selection in selections {
+                let (filetype_config, node) = visible_node_at(config, layers, &selection.range);
+                // Whole content is never deleted, i.e. with the cursor between items
+                if node.parent().is_some() {
+                    nodes.push((node, filetype_config, *selection));
+                }
+            }
+            // Consecutive siblings are deleted at once, so separators between them go away too
+            nodes.sort_by_key(|(node, ..)| (node.start_byte(), node.end_byte()));
+            let mut runs: Vec<(Node, Node, &FiletypeConfig, kakoune::Selection)> = Vec::new();
+            for (node, filetype_config, selection) in nodes {
+                match runs.last_mut() {
+                    Some((_, last, filetype_config, run_selection))
+                        if *last == node || edit::are_consecutive(filetype_config, *last, node) =>
+                    {
+                        *last = node;
+                        run_selection.main |= selection.main;
+                    }
+                    _ => runs.push((node, node, filetype_config, selection)),
+                }
+            }
+            for (first, last, filetype_config, selection) in runs {
+                let edit = edit::delete_nodes(filetype_config, first, last, &request.content);
+                edits.push(edit.like(&selection));
+            }
+            "no node to delete"
+        }
+        Op::SlurpForward | Op::SlurpBackward | Op::BarfForward | Op::BarfBackward => {
+            for selection in selections {
+                let (filetype_config, node) = visible_node_at(config, layers, &selection.range);
+                if let Some(edit) =
+                    find_container_of_interest(filetype_config, node).and_then(|container| {
+                        edit::move_delimiter(
+                            filetype_config,
+                            &request.op,
+                            container,
+                            &request.content,
+                        )
+                    })
+                {
+                    edits.push(edit.like(selection));
+                }
+            }
+            match request.op {
+                Op::SlurpForward | Op::SlurpBackward => "no sibling to slurp",
+                _ => "no element to barf",
+            }
+        }
+        Op::SpliceNode => {
+            for selection in selections {
+                let (filetype_config, node) = visible_node_at(config, layers, &selection.range);
+                if let Some(edit) =
+                    find_container_of_interest(filetype_config, node).and_then(|container| {
+                        edit::splice(filetype_config, container, &request.content)
+                    })
+                {
+                    edits.push(edit.like(selection));
+                }
+            }
+            "no container to splice"
+        }
+        Op::RaiseNode { kind } => {
+            for selection in selections {
+                let (filetype_config, node) = visible_node_at(config, layers, &selection.range);
+                let kinds = kind
+                    .as_ref()
+                    .map(|kind| filetype_config.resolve_alias(kind));
+                let mut parent = find_parent_of_interest(filetype_config, node, &kinds);
+                // Skip wrappers like argument lists and single statement blocks
+                while kinds.is_none() && is_wrapper(filetype_config, parent) {
+                    match find_parent_of_interest(filetype_config, parent, &None) {
+                        grandparent if grandparent != parent => parent = grandparent,
+                        _ => break,
+                    }
+                }
+                if parent.range() != node.range() {
+                    let text = edit::text(&request.content, &node).to_string();
+                    edits.push(
+                        Edit::new(parent.start_byte(), parent.end_byte(), text).like(selection),
+                    );
+                }
+            }
+            "no parent to raise into"
+        }
+        _ => unreachable!(),
+    };
+    Ok((edits, message))
+}
+
+/// Filetype config of the innermost layer at the range and the smallest node covering the range.
+fn node_at<'a>(
+    config: &'a Config,
+    layers: &'a [Layer],
+    range: &Range,
+) -> (&'a FiletypeConfig, Node<'a>) {
+    let layer = &layers[layer::innermost(layers, range)];
+    (
+        config.get_filetype_config(&layer.filetype),
+        tree::shrink_to_range(layer.tree.root_node(), range),
+    )
+}
+
+/// Like `node_at`, but the node is the closest visible one.
+fn visible_node_at<'a>(
+    config: &'a Config,
+    layers: &'a [Layer],
+    range: &Range,
+) -> (&'a FiletypeConfig, Node<'a>) {
+    let (filetype_config, node) = node_at(config, layers, range);
+    (
+        filetype_config,
+        traverse_up_to_node_which_matters(filetype_config, node),
+    )
+}
+
+/// Index of the innermost layer of each selection.
+fn innermost_layers(layers: &[Layer], selections: &[kakoune::Selection]) -> Vec<usize> {
+    selections
+        .iter()
+        .map(|selection| layer::innermost(layers, &selection.range))
+        .collect()
+}
+
+/// Make a value, i.e. compile a query, once per filetype of the layers with given indices.
+fn per_filetype<'a, T, F>(
+    layers: &'a [Layer],
+    indices: &[usize],
+    make: F,
+) -> Result<HashMap<&'a str, T>>
+where
+    F: Fn(&Layer) -> Result<T>,
+{
+    let mut values = HashMap::new();
+    for &index in indices {
+        let layer = &layers[index];
+        if !values.contains_key(&*layer.filetype) {
+            values.insert(&*layer.filetype, make(layer)?);
         }
     }
+    Ok(values)
 }
 
 fn select_nodes(node: &Node, kinds: &[String], new_ranges: &mut Vec<Range>) {
@@ -194,12 +719,34 @@ fn traverse_up_to_node_which_matters<'a>(
     current_node: Node<'a>,
 ) -> Node<'a> {
     let mut opt_node = Some(current_node);
-    while let Some(node) = opt_node.filter(|&n| !(n.is_named() && filetype_config.is_node_visible(n))) {
+    while let Some(node) =
+        opt_node.filter(|&n| !(n.is_named() && filetype_config.is_node_visible(n)))
+    {
         opt_node = node.parent();
     }
     opt_node.unwrap_or(current_node)
 }
 
+fn find_sibling_of_interest<'a>(
+    filetype_config: &FiletypeConfig,
+    current_node: Node<'a>,
+    kinds: &Option<Vec<String>>,
+    forward: bool,
+) -> Option<Node<'a>> {
+    let mut cursor = traverse_up_to_node_which_matters(filetype_config, current_node);
+    while let Some(node) = if forward {
+        cursor.next_named_sibling()
+    } else {
+        cursor.prev_named_sibling()
+    } {
+        if filetype_config.is_node_visible(node) && node_of_kinds(node, kinds) {
+            return Some(node);
+        }
+        cursor = node;
+    }
+    None
+}
+
 fn find_parent_of_interest<'a>(
     filetype_config: &FiletypeConfig,
     current_node: Node<'a>,
@@ -221,9 +768,288 @@ fn find_parent_of_interest<'a>(
     }
 }
 
+/// Closest delimited ancestor of `container` kinds when configured or visible one.
+fn find_container_of_interest<'a>(
+    filetype_config: &FiletypeConfig,
+    current_node: Node<'a>,
+) -> Option<Node<'a>> {
+    let kinds = filetype_config.group("container").map(<[_]>::to_vec);
+    let mut node = current_node;
+    loop {
+        let parent = find_parent_of_interest(filetype_config, node, &kinds);
+        if parent == node {
+            return None;
+        }
+        if edit::delimiters(filetype_config, parent).is_some() {
+            return Some(parent);
+        }
+        node = parent;
+    }
+}
+
+/// Whether the node only wraps a single child or a delimited part of its parent, like arguments
+/// of a call do.
+fn is_wrapper(filetype_config: &FiletypeConfig, node: Node) -> bool {
+    node.named_child_count() == 1
+        || (edit::delimiters(filetype_config, node).is_some()
+            && matches!(node.parent(), Some(parent) if edit::delimiters(filetype_config, parent).is_none()))
+}
+
+/// Visible node under the cursor which is smaller than the selection. When the cursor is on a
+/// delimiter, the closest named node on its inner side is taken instead.
+fn find_child_of_interest<'a>(
+    filetype_config: &FiletypeConfig,
+    current_node: Node<'a>,
+    selection: &kakoune::Selection,
+) -> Option<Node<'a>> {
+    let range = &selection.range;
+    let byte = selection.cursor();
+    let mut node = current_node;
+    loop {
+        let children = (0..node.child_count())
+            .filter_map(|i| node.child(i))
+            .collect::<Vec<_>>();
+        let index = children
+            .iter()
+            .position(|child| child.start_byte() <= byte && byte < child.end_byte())?;
+        let child = if children[index].is_named() {
+            children[index]
+        } else if selection.reversed {
+            *children[index + 1..]
+                .iter()
+                .find(|child| child.is_named())?
+        } else {
+            *children[..index]
+                .iter()
+                .rev()
+                .find(|child| child.is_named())?
+        };
+        if filetype_config.is_node_visible(child)
+            && range.start_byte <= child.start_byte()
+            && child.end_byte() <= range.end_byte
+            && child.range() != *range
+        {
+            return Some(child);
+        }
+        node = child;
+    }
+}
+
+fn breadcrumb_label(node: Node, content: &str) -> String {
+    match node.child_by_field_name("name") {
+        Some(name) => content[name.byte_range()]
+            .lines()
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_string(),
+        None => node.kind().to_string(),
+    }
+}
+
+fn error_message(node: Node, content: &str) -> String {
+    if node.is_missing() {
+        if node.is_named() {
+            format!("missing {}", node.kind())
+        } else {
+            format!("missing \"{}\"", node.kind())
+        }
+    } else {
+        match content[node.byte_range()]
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+        {
+            Some(text) => format!(
+                "unexpected \"{}\"",
+                text.chars().take(ERROR_TEXT_LENGTH).collect::<String>()
+            ),
+            None => "syntax error".to_string(),
+        }
+    }
+}
+
 fn node_of_kinds(node: Node, kinds: &Option<Vec<String>>) -> bool {
     kinds
         .as_ref()
         .and_then(|kinds| Some(kinds.iter().any(|x| x == node.kind())))
         .unwrap_or(true)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Content after the editing operation on selections of the given texts, the first one is main.
+    fn edit(content: &str, selected: &[&str], op: Op) -> Result<String> {
+        let config = Config::default();
+        let request = Request {
+            op,
+            filetype: "rust".to_string(),
+            buffer: String::new(),
+            buffile: String::new(),
+            timestamp: 0,
+            selections_desc: String::new(),
+            main_selection: 0,
+            content: content.to_string(),
+        };
+        let tree = parse(&mut Parser::new(), &config, &request, None)?;
+        let mut queries = layer::Queries::new();
+        let layers = layer::parse(&config, "rust", tree, content, &[], &mut queries)?;
+        let buffer = kakoune::Buffer::new(content);
+        let selections = selected
+            .iter()
+            .enumerate()
+            .map(|(i, text)| {
+                let start = content.find(text).expect("selected text is not in content");
+                kakoune::Selection {
+                    range: buffer.range(start, start + text.len()),
+                    reversed: false,
+                    main: i == 0,
+                }
+            })
+            .collect::<Vec<_>>();
+        let (edits, _) = make_edits(&config, &request, &layers, &selections)?;
+        Ok(edit::apply(content, &edit::normalize(edits)?).0)
+    }
+
+    #[test]
+    fn delete_node_with_separator() {
+        let content = "fn f() { g(a, b, c) }";
+        assert_eq!(
+            edit(content, &["a"], Op::DeleteNode).unwrap(),
+            "fn f() { g(b, c) }"
+        );
+        assert_eq!(
+            edit(content, &["c"], Op::DeleteNode).unwrap(),
+            "fn f() { g(a, b) }"
+        );
+    }
+
+    #[test]
+    fn delete_adjacent_nodes() {
+        let content = "fn f() { g(a, b, c, d) }";
+        assert_eq!(
+            edit(content, &["a", "b"], Op::DeleteNode).unwrap(),
+            "fn f() { g(c, d) }"
+        );
+        assert_eq!(
+            edit(content, &["b", "d"], Op::DeleteNode).unwrap(),
+            "fn f() { g(a, c) }"
+        );
+        assert_eq!(
+            edit(content, &["c", "d"], Op::DeleteNode).unwrap(),
+            "fn f() { g(a, b) }"
+        );
+    }
+
+    #[test]
+    fn delete_node_alone_on_line() {
+        let content = "fn f() {\n    let x = 1;\n    let y = 2;\n}\n";
+        assert_eq!(
+            edit(content, &["let x = 1;"], Op::DeleteNode).unwrap(),
+            "fn f() {\n    let y = 2;\n}\n"
+        );
+    }
+
+    #[test]
+    fn delete_node_keeps_root() {
+        let content = "fn a() {}\n\nfn b() {}\n";
+        assert_eq!(edit(content, &["\n\n"], Op::DeleteNode).unwrap(), content);
+    }
+
+    #[test]
+    fn delete_sole_element_with_line() {
+        let content = "fn f() {\n    g(\n        a,\n    )\n}\n";
+        assert_eq!(
+            edit(content, &["a"], Op::DeleteNode).unwrap(),
+            "fn f() {\n    g(\n    )\n}\n"
+        );
+    }
+
+    #[test]
+    fn move_delimiter_of_list() {
+        let content = "fn f() { g([a, b], c, d) }";
+        assert_eq!(
+            edit(content, &["a"], Op::SlurpForward).unwrap(),
+            "fn f() { g([a, b, c], d) }"
+        );
+        assert_eq!(
+            edit("fn f() { g(c, [a, b], d) }", &["a"], Op::SlurpBackward).unwrap(),
+            "fn f() { g([c, a, b], d) }"
+        );
+        assert_eq!(
+            edit(content, &["a"], Op::BarfForward).unwrap(),
+            "fn f() { g([a], b, c, d) }"
+        );
+        assert_eq!(
+            edit(content, &["a"], Op::BarfBackward).unwrap(),
+            "fn f() { g(a, [b], c, d) }"
+        );
+    }
+
+    #[test]
+    fn move_delimiter_of_block() {
+        let content = "fn f() { a(); { b(); c(); } d(); }";
+        assert_eq!(
+            edit(content, &["b"], Op::SlurpForward).unwrap(),
+            "fn f() { a(); { b(); c(); d(); } }"
+        );
+        assert_eq!(
+            edit(content, &["b"], Op::SlurpBackward).unwrap(),
+            "fn f() { { a(); b(); c(); } d(); }"
+        );
+        assert_eq!(
+            edit(content, &["b"], Op::BarfForward).unwrap(),
+            "fn f() { a(); { b(); } c(); d(); }"
+        );
+        assert_eq!(
+            edit(content, &["b"], Op::BarfBackward).unwrap(),
+            "fn f() { a(); b(); { c(); } d(); }"
+        );
+    }
+
+    #[test]
+    fn move_delimiter_keeps_only_element() {
+        let content = "fn f() { g([a], c) }";
+        assert_eq!(edit(content, &["a"], Op::BarfForward).unwrap(), content);
+        assert_eq!(edit(content, &["a"], Op::BarfBackward).unwrap(), content);
+    }
+
+    #[test]
+    fn move_delimiter_skips_statements() {
+        assert_eq!(
+            edit(
+                "fn f() { { let x = y; z(); } w(); }",
+                &["x"],
+                Op::SlurpForward
+            )
+            .unwrap(),
+            "fn f() { { let x = y; z(); w(); } }"
+        );
+    }
+
+    #[test]
+    fn splice_node_skips_statements() {
+        assert_eq!(
+            edit("fn f() { { let x = y; } }", &["x"], Op::SpliceNode).unwrap(),
+            "fn f() { let x = y; }"
+        );
+        assert_eq!(
+            edit("fn f() { { return x; } }", &["x"], Op::SpliceNode).unwrap(),
+            "fn f() { return x; }"
+        );
+    }
+
+    #[test]
+    fn raise_node_skips_wrappers() {
+        assert_eq!(
+            edit("fn f() { h(a, b) }", &["b"], Op::RaiseNode { kind: None }).unwrap(),
+            "fn f() { b }"
+        );
+        assert_eq!(
+            edit("fn f() { h([a, b]) }", &["b"], Op::RaiseNode { kind: None }).unwrap(),
+            "fn f() { h(b) }"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::query;
+use std::cmp::Reverse;
+use tree_sitter::{Node, QueryCursor, Range, Tree};
+
+const NAME_LENGTH: usize = 60;
+
+pub struct Symbol {
+    pub kind: String,
+    pub name: String,
+    pub range: Range,
+    pub depth: usize,
+}
+
+/// Collect symbols in document order. Symbol nodes are taken from `symbol` group of the filetype,
+/// from `symbols` query, or from function and class textobjects, whichever is found first.
+pub fn symbols(config: &Config, filetype: &str, tree: &Tree, content: &str) -> Result<Vec<Symbol>> {
+    let mut symbols = Vec::new();
+    match config.get_filetype_config(filetype).group("symbol") {
+        Some(kinds) => collect_nodes(tree.root_node(), kinds, content, &mut symbols),
+        None => {
+            let (source, captures) = match query::load(config, filetype, "symbols") {
+                Some(source) => (source, None),
+                None => (
+                    query::load(config, filetype, "textobjects").ok_or_else(|| {
+                        Error::Op(format!("no symbols for filetype {}", filetype))
+                    })?,
+                    Some(["function.outer", "class.outer"]),
+                ),
+            };
+            let query = query::compile(tree.language(), &source)?;
+            let captures = captures.map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| query::capture_index(&query, name))
+                    .collect::<Vec<_>>()
+            });
+            let text = content.as_bytes();
+            let mut cursor = QueryCursor::new();
+            for (query_match, index) in
+                cursor.captures(&query, tree.root_node(), |node| &text[node.byte_range()])
+            {
+                let capture = query_match.captures[index];
+                if captures
+                    .as_ref()
+                    .map(|c| c.contains(&capture.index))
+                    .unwrap_or(true)
+                {
+                    symbols.push(symbol(capture.node, content));
+                }
+            }
+        }
+    }
+    symbols.sort_by_key(|symbol| (symbol.range.start_byte, Reverse(symbol.range.end_byte)));
+    symbols.dedup_by_key(|symbol| (symbol.range.start_byte, symbol.range.end_byte));
+    let mut ends: Vec<usize> = Vec::new();
+    for symbol in &mut symbols {
+        while matches!(ends.last(), Some(&end) if end <= symbol.range.start_byte) {
+            ends.pop();
+        }
+        symbol.depth = ends.len();
+        ends.push(symbol.range.end_byte);
+    }
+    Ok(symbols)
+}
+
+fn collect_nodes(node: Node, kinds: &[String], content: &str, symbols: &mut Vec<Symbol>) {
+    if kinds.iter().any(|kind| kind == node.kind()) {
+        symbols.push(symbol(node, content));
+    }
+    for i in 0..node.named_child_count() {
+        if let Some(child) = node.named_child(i) {
+            collect_nodes(child, kinds, content, symbols);
+        }
+    }
+}
+
+fn symbol(node: Node, content: &str) -> Symbol {
+    let name = match node.child_by_field_name("name") {
+        Some(name) => &content[name.byte_range()],
+        None => content[node.byte_range()].lines().next().unwrap_or(""),
+    };
+    Symbol {
+        kind: node.kind().to_string(),
+        name: name.trim().chars().take(NAME_LENGTH).collect(),
+        range: node.range(),
+        depth: 0,
+    }
+}
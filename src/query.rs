@@ -0,0 +1,216 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use std::borrow::Cow;
+use tree_sitter::{Language, Node, Query, QueryCursor, QueryErrorKind, Range};
+
+pub fn load(config: &Config, filetype: &str, name: &str) -> Option<Cow<'static, str>> {
+    config
+        .query_dir()
+        .and_then(|dir| {
+            let path = std::path::Path::new(dir)
+                .join(filetype)
+                .join(format!("{}.scm", name));
+            std::fs::read_to_string(path).ok()
+        })
+        .map(Cow::Owned)
+        .or_else(|| builtin(config.grammar(filetype), name).map(Cow::Borrowed))
+}
+
+fn builtin(grammar: &str, name: &str) -> Option<&'static str> {
+    let source = match (grammar, name) {
+        #[cfg(feature = "bash")]
+        ("bash", "textobjects") => include_str!("../queries/bash/textobjects.scm"),
+        #[cfg(feature = "c")]
+        ("c", "textobjects") => include_str!("../queries/c/textobjects.scm"),
+        #[cfg(feature = "cpp")]
+        ("cpp", "textobjects") => include_str!("../queries/cpp/textobjects.scm"),
+        #[cfg(feature = "go")]
+        ("go", "textobjects") => include_str!("../queries/go/textobjects.scm"),
+        #[cfg(feature = "html")]
+        ("html", "injections") => include_str!("../queries/html/injections.scm"),
+        #[cfg(feature = "java")]
+        ("java", "textobjects") => include_str!("../queries/java/textobjects.scm"),
+        #[cfg(feature = "javascript")]
+        ("javascript", "textobjects") => include_str!("../queries/javascript/textobjects.scm"),
+        #[cfg(feature = "python")]
+        ("python", "textobjects") => include_str!("../queries/python/textobjects.scm"),
+        #[cfg(feature = "ruby")]
+        ("ruby", "textobjects") => include_str!("../queries/ruby/textobjects.scm"),
+        #[cfg(feature = "rust")]
+        ("rust", "highlights") => include_str!("../queries/rust/highlights.scm"),
+        #[cfg(feature = "rust")]
+        ("rust", "textobjects") => include_str!("../queries/rust/textobjects.scm"),
+        #[cfg(feature = "tsx")]
+        ("tsx", "textobjects") => include_str!("../queries/typescript/textobjects.scm"),
+        #[cfg(feature = "typescript")]
+        ("typescript", "textobjects") => include_str!("../queries/typescript/textobjects.scm"),
+        _ => return None,
+    };
+    Some(source)
+}
+
+pub fn compile(language: Language, source: &str) -> Result<Query> {
+    Query::new(language, source).map_err(|err| {
+        Error::Query(match err.kind {
+            QueryErrorKind::Syntax | QueryErrorKind::Structure => {
+                format!("syntax error at {}.{}", err.row, err.column + 1)
+            }
+            QueryErrorKind::NodeType => {
+                format!("unknown node type '{}' at line {}", err.message, err.row)
+            }
+            QueryErrorKind::Field => {
+                format!("unknown field '{}' at line {}", err.message, err.row)
+            }
+            QueryErrorKind::Capture => {
+                format!("unknown capture '{}' at line {}", err.message, err.row)
+            }
+            QueryErrorKind::Predicate => err.message,
+        })
+    })
+}
+
+pub fn capture_index(query: &Query, capture: &str) -> Option<u32> {
+    query
+        .capture_names()
+        .iter()
+        .position(|name| name == capture)
+        .map(|index| index as u32)
+}
+
+pub fn captures_in_range(
+    query: &Query,
+    root_node: Node,
+    content: &str,
+    range: &Range,
+    capture: Option<u32>,
+) -> Vec<Range> {
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(range.start_byte, range.end_byte);
+    let text = content.as_bytes();
+    cursor
+        .captures(query, root_node, |node| &text[node.byte_range()])
+        .map(|(query_match, index)| query_match.captures[index])
+        .filter(|c| capture.is_none() || capture == Some(c.index))
+        .map(|c| c.node.range())
+        .filter(|r| range.start_byte <= r.start_byte && r.end_byte <= range.end_byte)
+        .collect()
+}
+
+/// Smallest capture around the range. Several nodes captured by one match, i.e. everything between
+/// braces of a block, make a single capture spanning all of them.
+pub fn smallest_capture_containing_range(
+    query: &Query,
+    root_node: Node,
+    content: &str,
+    range: &Range,
+    capture: u32,
+) -> Option<Range> {
+    // Delimiters around the captured nodes are outside of the range, so the whole tree is matched
+    let mut cursor = QueryCursor::new();
+    let text = content.as_bytes();
+    cursor
+        .matches(query, root_node, |node| &text[node.byte_range()])
+        .filter_map(|query_match| {
+            let mut nodes = query_match
+                .captures
+                .iter()
+                .filter(|c| c.index == capture)
+                .map(|c| c.node.range());
+            let first = nodes.next()?;
+            Some(nodes.fold(first, |span, r| Range {
+                start_byte: span.start_byte.min(r.start_byte),
+                end_byte: span.end_byte.max(r.end_byte),
+                start_point: span.start_point.min(r.start_point),
+                end_point: span.end_point.max(r.end_point),
+            }))
+        })
+        .filter(|r| r.start_byte <= range.start_byte && range.end_byte <= r.end_byte)
+        .min_by_key(|r| r.end_byte - r.start_byte)
+}
+
+/// Captured ranges with faces given by capture index, the first capture of a node wins.
+pub fn highlights<'a>(
+    query: &Query,
+    root_node: Node,
+    content: &str,
+    faces: &[Option<&'a str>],
+) -> Vec<(Range, &'a str)> {
+    let mut cursor = QueryCursor::new();
+    let text = content.as_bytes();
+    let mut highlights: Vec<(Range, &str)> = Vec::new();
+    for (query_match, index) in cursor.captures(query, root_node, |node| &text[node.byte_range()]) {
+        let capture = query_match.captures[index];
+        if let Some(face) = faces[capture.index as usize] {
+            let range = capture.node.range();
+            if range.start_byte < range.end_byte
+                && !matches!(highlights.last(), Some((last, _)) if *last == range)
+            {
+                highlights.push((range, face));
+            }
+        }
+    }
+    highlights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi;
+    use tree_sitter::Parser;
+
+    const GRAMMARS: [&str; 12] = [
+        "bash",
+        "c",
+        "cpp",
+        "go",
+        "html",
+        "java",
+        "javascript",
+        "python",
+        "ruby",
+        "rust",
+        "tsx",
+        "typescript",
+    ];
+
+    #[test]
+    fn builtin_queries_compile() {
+        let config = Config::default();
+        for grammar in &GRAMMARS {
+            for name in &["highlights", "injections", "textobjects"] {
+                if let Some(source) = builtin(grammar, name) {
+                    let language = ffi::load_language(&config, grammar).unwrap();
+                    if let Err(err) = compile(language, source) {
+                        panic!("{}/{}.scm: {}", grammar, name, err);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inner_textobject_spans_block_content() {
+        let config = Config::default();
+        let language = ffi::load_language(&config, "rust").unwrap();
+        let content = "fn f() {\n    a();\n    b();\n}\n";
+        let mut parser = Parser::new();
+        parser.set_language(language).unwrap();
+        let tree = parser.parse(content, None).unwrap();
+        let query = compile(language, builtin("rust", "textobjects").unwrap()).unwrap();
+        let start = content.find("a()").unwrap();
+        let range = tree
+            .root_node()
+            .descendant_for_byte_range(start, start)
+            .unwrap()
+            .range();
+        let inner = smallest_capture_containing_range(
+            &query,
+            tree.root_node(),
+            content,
+            &range,
+            capture_index(&query, "function.inner").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(&content[inner.start_byte..inner.end_byte], "a();\n    b();");
+    }
+}
@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::kakoune;
+use crate::layer::{self, Layer, Queries};
+use crate::tree::byte_to_point;
+use crate::{Op, Request};
+use slog_scope::{debug, error, info};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use tree_sitter::{InputEdit, Parser};
+
+struct Buffer {
+    filetype: String,
+    timestamp: usize,
+    content: String,
+    layers: Vec<Layer>,
+}
+
+pub fn run(config: &Config, socket: &str) -> Result<()> {
+    let _ = std::fs::remove_file(socket);
+    let listener =
+        UnixListener::bind(socket).map_err(|err| Error::Server(format!("{}: {}", socket, err)))?;
+    info!("listening on {}", socket);
+    let mut parser = Parser::new();
+    let mut buffers: HashMap<String, Buffer> = HashMap::new();
+    let mut queries = Queries::new();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(mut stream) => {
+                let mut request = String::new();
+                let response = match stream.read_to_string(&mut request) {
+                    Ok(_) => crate::parse_request(&request).and_then(|request| {
+                        if let Op::CloseBuffer = request.op {
+                            debug!("dropping {}", request.buffer);
+                            buffers.remove(&request.buffer);
+                            return Ok(String::new());
+                        }
+                        let config = config.for_buffer(&request.buffile)?;
+                        let layers =
+                            reparse(&mut parser, &config, &mut buffers, &mut queries, &request)?;
+                        crate::handle_request(&config, &request, layers)
+                    }),
+                    Err(err) => Err(Error::Request(err.to_string())),
+                };
+                let response = match response {
+                    Ok(response) => format!("0\n{}", response),
+                    Err(err) => {
+                        error!("{}", err);
+                        format!("{}\n{}", err.exit_code(), kakoune::fail(&err.to_string()))
+                    }
+                };
+                if let Err(err) = stream.write_all(response.as_bytes()) {
+                    error!("failed to write response: {}", err);
+                }
+            }
+            Err(err) => error!("failed to accept connection: {}", err),
+        }
+    }
+    Ok(())
+}
+
+pub fn connect(socket: &str, request: &str) -> Option<(i32, String)> {
+    let mut stream = UnixStream::connect(socket).ok()?;
+    stream.write_all(request.as_bytes()).ok()?;
+    stream.shutdown(Shutdown::Write).ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    let mut response = response.splitn(2, '\n');
+    let code = response.next()?.parse().ok()?;
+    Some((code, response.next()?.to_string()))
+}
+
+fn reparse<'a>(
+    parser: &mut Parser,
+    config: &Config,
+    buffers: &'a mut HashMap<String, Buffer>,
+    queries: &mut Queries,
+    request: &Request,
+) -> Result<&'a [Layer]> {
+    let layers = match buffers.remove(&request.buffer) {
+        Some(buffer)
+            if buffer.filetype == request.filetype
+                && buffer.timestamp == request.timestamp
+                && buffer.content == request.content =>
+        {
+            debug!("reusing tree of {}@{}", request.buffer, request.timestamp);
+            buffer.layers
+        }
+        Some(mut buffer) if buffer.filetype == request.filetype => {
+            debug!(
+                "reparsing {} from {} to {}",
+                request.buffer, buffer.timestamp, request.timestamp
+            );
+            layer::edit(
+                &mut buffer.layers,
+                &input_edit(&buffer.content, &request.content),
+            );
+            let tree = crate::parse(parser, config, request, Some(&buffer.layers[0].tree))?;
+            layer::parse(
+                config,
+                &request.filetype,
+                tree,
+                &request.content,
+                &buffer.layers,
+                queries,
+            )?
+        }
+        _ => {
+            debug!("parsing {}@{}", request.buffer, request.timestamp);
+            let tree = crate::parse(parser, config, request, None)?;
+            layer::parse(
+                config,
+                &request.filetype,
+                tree,
+                &request.content,
+                &[],
+                queries,
+            )?
+        }
+    };
+    buffers.insert(
+        request.buffer.clone(),
+        Buffer {
+            filetype: request.filetype.clone(),
+            timestamp: request.timestamp,
+            content: request.content.clone(),
+            layers,
+        },
+    );
+    Ok(&buffers[&request.buffer].layers)
+}
+
+fn input_edit(old: &str, new: &str) -> InputEdit {
+    let old = old.as_bytes();
+    let new = new.as_bytes();
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_end_byte = old.len() - suffix;
+    let new_end_byte = new.len() - suffix;
+    InputEdit {
+        start_byte: prefix,
+        old_end_byte,
+        new_end_byte,
+        start_position: byte_to_point(old, prefix),
+        old_end_position: byte_to_point(old, old_end_byte),
+        new_end_position: byte_to_point(new, new_end_byte),
+    }
+}
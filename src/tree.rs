@@ -1,4 +1,4 @@
-use tree_sitter::{Node, Range};
+use tree_sitter::{Node, Point, Range, TreeCursor};
 
 pub fn named_children<'a>(node: &'a Node) -> impl Iterator<Item = Node<'a>> {
     (0..node.child_count()).map(move |i| node.child(i).unwrap())
@@ -62,6 +62,59 @@ pub fn nodes_in_range<'a>(root_node: Node<'a>, range: &Range) -> Vec<Node<'a>> {
     nodes
 }
 
+/// Nodes of the subtree in document order, parents before their children. Children of a node are
+/// skipped when `descend` returns false for it.
+pub fn descendants<'a, F>(node: Node<'a>, descend: F) -> Descendants<'a, F>
+where
+    F: Fn(&Node<'a>) -> bool,
+{
+    Descendants {
+        cursor: node.walk(),
+        descend,
+        done: false,
+    }
+}
+
+pub struct Descendants<'a, F> {
+    cursor: TreeCursor<'a>,
+    descend: F,
+    done: bool,
+}
+
+impl<'a, F> Iterator for Descendants<'a, F>
+where
+    F: Fn(&Node<'a>) -> bool,
+{
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        if self.done {
+            return None;
+        }
+        let node = self.cursor.node();
+        if !((self.descend)(&node) && self.cursor.goto_first_child()) {
+            while !self.cursor.goto_next_sibling() {
+                if !self.cursor.goto_parent() {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+        Some(node)
+    }
+}
+
+fn is_error(node: &Node) -> bool {
+    node.is_error() || node.is_missing()
+}
+
+/// `ERROR` and `MISSING` nodes in document order, descending only into subtrees with errors.
+pub fn errors(root_node: Node) -> Vec<Node> {
+    descendants(root_node, |node| node.has_error() && !is_error(node))
+        .filter(is_error)
+        .collect()
+}
+
 fn highest_node_of_same_range<'a>(current_node: Node<'a>) -> Node<'a> {
     let start_byte = current_node.start_byte();
     let end_byte = current_node.end_byte();
@@ -77,3 +130,14 @@ fn highest_node_of_same_range<'a>(current_node: Node<'a>) -> Node<'a> {
     }
     node
 }
+
+pub fn byte_to_point(content: &[u8], byte: usize) -> Point {
+    let content = &content[..byte];
+    let row = content.iter().filter(|&&c| c == b'\n').count();
+    let column = content
+        .iter()
+        .rev()
+        .position(|&c| c == b'\n')
+        .unwrap_or(byte);
+    Point::new(row, column)
+}